@@ -0,0 +1,623 @@
+//! HTTP front end for the engine, with the same routes and response shapes
+//! as the NestJS backend so the Next.js client can point at either, plus a
+//! WebSocket market-data feed of the live engine.
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Query, Request, State};
+use axum::http::{HeaderValue, Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use backend_rust_task::{
+    AdminAction, DepthUpdate, EngineError, ExecutionReport, Instrument, InstrumentRegistry,
+    MatcherEngine, Order, OrderBook, Side, SweepEstimate, SweepSize, SystemClock, Trade,
+};
+use clap::Parser;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use tokio::fs;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+/// Messages a slow feed client may fall behind by before it is resynced.
+const FEED_CAPACITY: usize = 1024;
+
+#[derive(Parser)]
+#[command(about = "Serves the matching engine over HTTP")]
+struct Cli {
+    /// Port to listen on; 0 picks a free one.
+    #[arg(short, long, default_value_t = 3001)]
+    port: u16,
+    /// Address to listen on; `0.0.0.0` serves every interface.
+    #[arg(long, default_value = "127.0.0.1")]
+    host: String,
+    /// Origin browsers may call the API from; may be repeated. Defaults to
+    /// the Next.js frontend, run with `npm run dev` (3000) or its docker
+    /// compose file (4000).
+    #[arg(
+        long = "cors-origin",
+        value_name = "ORIGIN",
+        default_values = ["http://localhost:3000", "http://localhost:4000"],
+        value_parser = parse_origin
+    )]
+    cors_origins: Vec<HeaderValue>,
+    /// Bearer token the `/api/instruments/{halt,resume,delist}` routes
+    /// require. Without one they are refused.
+    #[arg(long, value_name = "TOKEN")]
+    admin_token: Option<String>,
+    /// Orders file read by `GET /api/process-file`.
+    #[arg(short, long, default_value = "orders.json")]
+    input: PathBuf,
+    /// Directory `GET /api/process-file` writes its output files to.
+    #[arg(short, long, default_value = ".")]
+    output: PathBuf,
+    /// Journal of the live engine: replayed on startup, then appended to
+    /// before each order is applied.
+    #[arg(short, long)]
+    journal: Option<PathBuf>,
+    /// Directory of snapshots: the latest is restored on startup, before the
+    /// journal entries that follow it.
+    #[arg(short, long, requires = "journal")]
+    snapshots: Option<PathBuf>,
+    /// JSON file of the pairs to list, with their trading rules; orders for
+    /// any other pair are rejected by every endpoint.
+    #[arg(long)]
+    instruments: Option<PathBuf>,
+}
+
+fn parse_origin(value: &str) -> Result<HeaderValue, String> {
+    HeaderValue::from_str(value).map_err(|err| format!("{value}: {err}"))
+}
+
+/// What the server shares between requests: the paths of the file endpoint,
+/// the engine behind the stateful ones and the feed it publishes to.
+struct AppState {
+    input: PathBuf,
+    output: PathBuf,
+    engine: Mutex<MatcherEngine>,
+    snapshots: Option<PathBuf>,
+    /// Also handed, as listed at startup, to the fresh engine of each batch.
+    registry: Option<InstrumentRegistry>,
+    /// Only sent to while `engine` is locked, so a snapshot taken under the
+    /// lock splits the feed cleanly into before and after.
+    feed: broadcast::Sender<FeedMessage>,
+    admin_token: Option<String>,
+}
+
+impl AppState {
+    /// Locks the engine. A request that panicked while holding the lock
+    /// doesn't take every later request down with it.
+    fn engine(&self) -> MutexGuard<'_, MatcherEngine> {
+        self.engine.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+type SharedState = Arc<AppState>;
+
+/// Runs `f` with the engine locked, on the blocking pool: orders and admin
+/// actions fsync the journal under the lock, and neither that nor waiting
+/// for the lock may hold up an async worker.
+async fn with_engine<T, F>(state: &SharedState, f: F) -> Result<T, ApiError>
+where
+    T: Send + 'static,
+    F: FnOnce(&mut MatcherEngine, &AppState) -> T + Send + 'static,
+{
+    let state = Arc::clone(state);
+    tokio::task::spawn_blocking(move || f(&mut state.engine(), &state))
+        .await
+        .map_err(|err| ApiError(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+/// Response of the batch endpoints, as the NestJS backend returns it.
+#[derive(Serialize)]
+struct BatchOutput {
+    orderbooks: Vec<Order>,
+    trades: Vec<Trade>,
+}
+
+/// What a single order submitted to the live engine produced.
+#[derive(Serialize)]
+struct SubmitOutput {
+    trades: Vec<Trade>,
+    #[serde(rename = "executionReports")]
+    execution_reports: Vec<ExecutionReport>,
+}
+
+/// An instrument after an admin action, with the cancellations a delisting
+/// caused.
+#[derive(Serialize)]
+struct AdminOutput {
+    instrument: Instrument,
+    #[serde(rename = "executionReports")]
+    execution_reports: Vec<ExecutionReport>,
+}
+
+#[derive(Deserialize)]
+struct BookQuery {
+    pair: String,
+}
+
+#[derive(Deserialize)]
+struct DepthQuery {
+    pair: String,
+    /// Levels per side; all of them when absent.
+    levels: Option<usize>,
+    grouping: Option<Decimal>,
+}
+
+#[derive(Deserialize)]
+struct SweepQuery {
+    pair: String,
+    side: Side,
+    /// Base quantity to sweep; give this or `notional`.
+    amount: Option<Decimal>,
+    /// Quote quantity to spend or raise.
+    notional: Option<Decimal>,
+}
+
+/// One WebSocket text frame of the market-data feed, tagged by `type`.
+#[derive(Serialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum FeedMessage {
+    /// Every level of a pair, sent on subscribe and after falling behind.
+    Snapshot(DepthUpdate),
+    /// Levels that changed, to apply on top of the snapshot in `seq` order.
+    Depth(DepthUpdate),
+    /// A trade, with the `seq` of the depth update its input produced.
+    Trade {
+        seq: u64,
+        trade: Trade,
+    },
+    Error {
+        message: String,
+    },
+}
+
+impl FeedMessage {
+    /// Pair and depth `seq` of a published message.
+    fn position(&self) -> Option<(&str, u64)> {
+        match self {
+            FeedMessage::Depth(update) => Some((update.pair(), update.seq())),
+            FeedMessage::Trade { seq, trade } => Some((trade.pair(), *seq)),
+            FeedMessage::Snapshot(_) | FeedMessage::Error { .. } => None,
+        }
+    }
+}
+
+/// What a feed client sends, tagged by `op`.
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+enum FeedRequest {
+    Subscribe { pair: String },
+    Unsubscribe { pair: String },
+}
+
+/// An error sent back as `{"message": ...}` with its status code.
+struct ApiError(StatusCode, String);
+
+fn status_of(err: &EngineError) -> StatusCode {
+    match err {
+        EngineError::Rejected { .. } => StatusCode::UNPROCESSABLE_ENTITY,
+        EngineError::Json(_) | EngineError::Csv(_) => StatusCode::BAD_REQUEST,
+        EngineError::UnknownInstrument { .. } => StatusCode::NOT_FOUND,
+        EngineError::InvalidInstrument { .. } => StatusCode::CONFLICT,
+        EngineError::Io(_)
+        | EngineError::CorruptJournal { .. }
+        | EngineError::UnsupportedSnapshot { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+    }
+}
+
+impl<E: Into<EngineError>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        let err = err.into();
+        ApiError(status_of(&err), err.to_string())
+    }
+}
+
+fn no_book(pair: &str) -> ApiError {
+    ApiError(StatusCode::NOT_FOUND, format!("No order book for {pair}"))
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = serde_json::json!({ "message": self.1 });
+        (self.0, Json(body)).into_response()
+    }
+}
+
+/// Matches a whole batch on a fresh engine, on the blocking pool like
+/// `with_engine`. Rejected rows are logged and skipped, as in the CLI.
+async fn run_batch(
+    state: &SharedState,
+    rows: Vec<serde_json::Value>,
+) -> Result<BatchOutput, ApiError> {
+    let registry = state.registry.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut engine = MatcherEngine::with_clock(Box::new(SystemClock));
+        if let Some(registry) = registry {
+            engine.set_registry(registry);
+        }
+        for (row, value) in (1..).zip(rows) {
+            if let Err(err) = engine.ingest_value(value) {
+                eprintln!("Row {row}: {err}");
+            }
+        }
+        let (orderbooks, trades) = engine.finish();
+        BatchOutput { orderbooks, trades }
+    })
+    .await
+    .map_err(|err| ApiError(StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))
+}
+
+/// `GET /api/process-file`: matches the input file and, like the NestJS
+/// service, also writes the result to the output directory.
+async fn process_file(State(state): State<SharedState>) -> Result<Json<BatchOutput>, ApiError> {
+    let text = fs::read_to_string(&state.input).await?;
+    let rows = serde_json::from_str(&text)?;
+    let output = run_batch(&state, rows).await?;
+    fs::create_dir_all(&state.output).await?;
+    let orderbook = serde_json::to_string_pretty(&output.orderbooks)?;
+    let trades = serde_json::to_string_pretty(&output.trades)?;
+    fs::write(state.output.join("orderbook.json"), orderbook).await?;
+    fs::write(state.output.join("trades.json"), trades).await?;
+    Ok(Json(output))
+}
+
+/// `POST /api/process-json`: matches the orders in the body, statelessly.
+async fn process_json(
+    State(state): State<SharedState>,
+    Json(rows): Json<Vec<serde_json::Value>>,
+) -> Result<Json<BatchOutput>, ApiError> {
+    if rows.is_empty() {
+        return Err(ApiError(
+            StatusCode::BAD_REQUEST,
+            "Body must be a non-empty array of orders".to_string(),
+        ));
+    }
+    Ok(Json(run_batch(&state, rows).await?))
+}
+
+/// `POST /api/orders`: feeds one order to the live engine and returns the
+/// trades and reports it caused. A rejection is 422 with its REJECT report.
+async fn submit_order(
+    State(state): State<SharedState>,
+    Json(value): Json<serde_json::Value>,
+) -> Result<Response, ApiError> {
+    with_engine(&state, move |engine, state| {
+        let result = engine.ingest_value(value);
+        let (trades, execution_reports) = engine.drain();
+        // Sending only fails when nobody is subscribed.
+        for trade in &trades {
+            let seq = engine.book(trade.pair()).map_or(0, OrderBook::depth_seq);
+            let trade = trade.clone();
+            let _ = state.feed.send(FeedMessage::Trade { seq, trade });
+        }
+        for update in engine.drain_depth() {
+            let _ = state.feed.send(FeedMessage::Depth(update));
+        }
+        let status = result.as_ref().err().map_or(StatusCode::OK, status_of);
+        let output = SubmitOutput {
+            trades,
+            execution_reports,
+        };
+        (status, Json(output)).into_response()
+    })
+    .await
+}
+
+/// `GET /api/orderbook?pair=BTC/USDC`: the live book of one pair.
+async fn get_book(
+    State(state): State<SharedState>,
+    Query(query): Query<BookQuery>,
+) -> Result<Json<Order>, ApiError> {
+    with_engine(&state, move |engine, _| {
+        let book = engine
+            .book(&query.pair)
+            .ok_or_else(|| no_book(&query.pair))?;
+        Ok(Json(book.normalize()))
+    })
+    .await?
+}
+
+/// Snapshots `pairs` and records how far each goes, so feed messages they
+/// already cover are skipped. `None` if the engine couldn't be read.
+async fn subscribe(
+    state: &SharedState,
+    synced: &mut HashMap<String, u64>,
+    pairs: Vec<String>,
+) -> Option<Vec<FeedMessage>> {
+    let snapshots = with_engine(state, move |engine, _| {
+        pairs
+            .iter()
+            .map(|pair| engine.depth_snapshot(pair))
+            .collect::<Vec<_>>()
+    })
+    .await
+    .ok()?;
+    let messages = snapshots
+        .into_iter()
+        .map(|snapshot| {
+            synced.insert(snapshot.pair().to_string(), snapshot.seq());
+            FeedMessage::Snapshot(snapshot)
+        })
+        .collect();
+    Some(messages)
+}
+
+/// `GET /ws`: the market-data feed. Clients send
+/// `{"op":"subscribe","pair":"BTC/USDC"}` and get a snapshot, then the
+/// trades and depth updates of that pair as they happen.
+async fn feed(ws: WebSocketUpgrade, State(state): State<SharedState>) -> Response {
+    ws.on_upgrade(move |socket| serve_feed(socket, state))
+}
+
+async fn serve_feed(mut socket: WebSocket, state: SharedState) {
+    let mut feed = state.feed.subscribe();
+    let mut synced = HashMap::new();
+    loop {
+        let messages = tokio::select! {
+            request = socket.recv() => match request {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str(&text) {
+                    Ok(FeedRequest::Subscribe { pair }) => {
+                        let Some(messages) = subscribe(&state, &mut synced, vec![pair]).await
+                        else {
+                            return;
+                        };
+                        messages
+                    }
+                    Ok(FeedRequest::Unsubscribe { pair }) => {
+                        synced.remove(&pair);
+                        continue;
+                    }
+                    Err(err) => vec![FeedMessage::Error {
+                        message: err.to_string(),
+                    }],
+                },
+                Some(Ok(_)) => continue,
+                Some(Err(_)) | None => return,
+            },
+            published = feed.recv() => match published {
+                Ok(message) => match message.position() {
+                    Some((pair, seq)) if synced.get(pair).is_some_and(|&s| seq > s) => {
+                        vec![message]
+                    }
+                    _ => continue,
+                },
+                // Updates were dropped: start every pair over from a snapshot.
+                Err(RecvError::Lagged(_)) => {
+                    let pairs = synced.keys().cloned().collect();
+                    let Some(messages) = subscribe(&state, &mut synced, pairs).await else {
+                        return;
+                    };
+                    messages
+                }
+                Err(RecvError::Closed) => return,
+            },
+        };
+        for message in messages {
+            let Ok(text) = serde_json::to_string(&message) else {
+                continue;
+            };
+            if socket.send(Message::Text(text.into())).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// `GET /api/depth?pair=BTC/USDC&levels=10&grouping=10`: per-level totals of
+/// the live book of one pair.
+async fn get_depth(
+    State(state): State<SharedState>,
+    Query(query): Query<DepthQuery>,
+) -> Result<Json<DepthUpdate>, ApiError> {
+    with_engine(&state, move |engine, _| {
+        let book = engine
+            .book(&query.pair)
+            .ok_or_else(|| no_book(&query.pair))?;
+        let levels = query.levels.unwrap_or(usize::MAX);
+        Ok(Json(book.depth(levels, query.grouping)))
+    })
+    .await?
+}
+
+/// `GET /api/sweep?pair=BTC/USDC&side=BUY&amount=2`: what sweeping the live
+/// book would cost right now, without trading.
+async fn get_sweep(
+    State(state): State<SharedState>,
+    Query(query): Query<SweepQuery>,
+) -> Result<Json<SweepEstimate>, ApiError> {
+    let size = match (query.amount, query.notional) {
+        (Some(amount), None) => SweepSize::Amount(amount),
+        (None, Some(notional)) => SweepSize::Notional(notional),
+        _ => {
+            return Err(ApiError(
+                StatusCode::BAD_REQUEST,
+                "Give exactly one of amount and notional".to_string(),
+            ));
+        }
+    };
+    with_engine(&state, move |engine, _| {
+        let estimate = engine
+            .simulate_sweep(&query.pair, query.side, size)
+            .ok_or_else(|| no_book(&query.pair))?
+            .map_err(|reason| ApiError(StatusCode::BAD_REQUEST, reason.to_string()))?;
+        Ok(Json(estimate))
+    })
+    .await?
+}
+
+/// `POST /api/snapshot`: snapshots the live engine, so a restart only
+/// replays the journal from here.
+async fn take_snapshot(
+    State(state): State<SharedState>,
+) -> Result<Json<serde_json::Value>, ApiError> {
+    let dir = state.snapshots.clone().ok_or_else(|| {
+        ApiError(
+            StatusCode::CONFLICT,
+            "Server was started without --snapshots".to_string(),
+        )
+    })?;
+    let path = with_engine(&state, move |engine, _| engine.snapshot(dir)).await??;
+    Ok(Json(serde_json::json!({ "path": path })))
+}
+
+/// `GET /api/instruments`: every listed pair with its status and rules.
+async fn list_instruments(
+    State(state): State<SharedState>,
+) -> Result<Json<Vec<Instrument>>, ApiError> {
+    with_engine(&state, |engine, _| {
+        Json(engine.instruments().cloned().collect())
+    })
+    .await
+}
+
+/// Applies `action` to the pair in the body and publishes the depth updates
+/// of any orders it cancelled.
+async fn administer(
+    state: &SharedState,
+    action: AdminAction,
+    query: BookQuery,
+) -> Result<Json<AdminOutput>, ApiError> {
+    with_engine(state, move |engine, state| {
+        match action {
+            AdminAction::HALT => engine.halt(&query.pair)?,
+            AdminAction::RESUME => engine.resume(&query.pair)?,
+            AdminAction::DELIST => engine.delist(&query.pair)?,
+        }
+        let (_, execution_reports) = engine.drain();
+        for update in engine.drain_depth() {
+            let _ = state.feed.send(FeedMessage::Depth(update));
+        }
+        let instrument = engine
+            .instrument(&query.pair)
+            .cloned()
+            .expect("Admin actions only succeed on listed pairs");
+        Ok(Json(AdminOutput {
+            instrument,
+            execution_reports,
+        }))
+    })
+    .await?
+}
+
+/// `POST /api/instruments/halt` with `{"pair": "BTC/USDC"}`: stops new and
+/// amended orders for the pair; cancels still go through.
+async fn halt_instrument(
+    State(state): State<SharedState>,
+    Json(query): Json<BookQuery>,
+) -> Result<Json<AdminOutput>, ApiError> {
+    administer(&state, AdminAction::HALT, query).await
+}
+
+/// `POST /api/instruments/resume`: lets a halted pair trade again.
+async fn resume_instrument(
+    State(state): State<SharedState>,
+    Json(query): Json<BookQuery>,
+) -> Result<Json<AdminOutput>, ApiError> {
+    administer(&state, AdminAction::RESUME, query).await
+}
+
+/// `POST /api/instruments/delist`: cancels the pair's resting orders and
+/// closes it for good.
+async fn delist_instrument(
+    State(state): State<SharedState>,
+    Json(query): Json<BookQuery>,
+) -> Result<Json<AdminOutput>, ApiError> {
+    administer(&state, AdminAction::DELIST, query).await
+}
+
+/// Lets a request through to an admin route only with
+/// `Authorization: Bearer <--admin-token>`.
+async fn require_admin(State(state): State<SharedState>, request: Request, next: Next) -> Response {
+    let Some(token) = &state.admin_token else {
+        let message = "Server was started without --admin-token".to_string();
+        return ApiError(StatusCode::FORBIDDEN, message).into_response();
+    };
+    let given = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    // Compares every byte, so the time taken doesn't say how much matched.
+    let matches = given.len() == token.len()
+        && given
+            .bytes()
+            .zip(token.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0;
+    if !matches {
+        let message = "Missing or wrong admin token".to_string();
+        return ApiError(StatusCode::UNAUTHORIZED, message).into_response();
+    }
+    next.run(request).await
+}
+
+fn router(state: SharedState, cors_origins: Vec<HeaderValue>) -> Router {
+    let admin = Router::new()
+        .route("/api/instruments/halt", post(halt_instrument))
+        .route("/api/instruments/resume", post(resume_instrument))
+        .route("/api/instruments/delist", post(delist_instrument))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::clone(&state),
+            require_admin,
+        ));
+    let cors = CorsLayer::new()
+        .allow_origin(AllowOrigin::list(cors_origins))
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::CONTENT_TYPE, header::AUTHORIZATION]);
+    Router::new()
+        .route("/api/process-file", get(process_file))
+        .route("/api/process-json", post(process_json))
+        .route("/api/orders", post(submit_order))
+        .route("/api/orderbook", get(get_book))
+        .route("/api/depth", get(get_depth))
+        .route("/api/sweep", get(get_sweep))
+        .route("/api/snapshot", post(take_snapshot))
+        .route("/api/instruments", get(list_instruments))
+        .merge(admin)
+        .route("/ws", get(feed))
+        .layer(cors)
+        .with_state(state)
+}
+
+#[tokio::main]
+async fn main() -> Result<(), EngineError> {
+    let cli = Cli::parse();
+    let mut engine = MatcherEngine::with_clock(Box::new(SystemClock));
+    engine.set_depth_updates(true);
+    let registry = cli
+        .instruments
+        .as_ref()
+        .map(InstrumentRegistry::load)
+        .transpose()?;
+    if let Some(registry) = &registry {
+        engine.set_registry(registry.clone());
+    }
+    if let Some(path) = &cli.journal {
+        let replayed = match &cli.snapshots {
+            Some(dir) => engine.restore(dir, path)?,
+            None => engine.open_journal(path)?,
+        };
+        eprintln!("Replayed {replayed} order(s) from {}", path.display());
+    }
+    let state = Arc::new(AppState {
+        input: cli.input,
+        output: cli.output,
+        engine: Mutex::new(engine),
+        snapshots: cli.snapshots,
+        registry,
+        feed: broadcast::channel(FEED_CAPACITY).0,
+        admin_token: cli.admin_token,
+    });
+    let listener = tokio::net::TcpListener::bind((cli.host.as_str(), cli.port)).await?;
+    eprintln!("Listening on {}", listener.local_addr()?);
+    axum::serve(listener, router(state, cli.cors_origins)).await?;
+    Ok(())
+}
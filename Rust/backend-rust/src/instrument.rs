@@ -0,0 +1,201 @@
+//! The pairs an engine lists: each one's base and quote asset, whether it is
+//! trading, and the price and amount grids orders must sit on and the size
+//! limits they must respect, checked before an order can match.
+
+use crate::{EngineError, RejectReason};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+/// Rules for one pair. Every rule is optional, and a pair without any
+/// accepts whatever positive price and amount it is given.
+#[derive(Deserialize, Serialize, Clone, Debug, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InstrumentRules {
+    /// Prices must be a multiple of this.
+    pub tick_size: Option<Decimal>,
+    /// Amounts must be a multiple of this.
+    pub lot_size: Option<Decimal>,
+    pub min_amount: Option<Decimal>,
+    pub max_amount: Option<Decimal>,
+    /// Smallest `price * amount` of a LIMIT order.
+    pub min_notional: Option<Decimal>,
+}
+
+impl InstrumentRules {
+    /// Every rule that is set must be positive, and the amount range must
+    /// not be empty.
+    pub fn validate(&self, pair: &str) -> Result<(), EngineError> {
+        let fields = [
+            ("tick_size", self.tick_size),
+            ("lot_size", self.lot_size),
+            ("min_amount", self.min_amount),
+            ("max_amount", self.max_amount),
+            ("min_notional", self.min_notional),
+        ];
+        for (field, value) in fields {
+            if value.is_some_and(|v| v <= Decimal::ZERO) {
+                return Err(EngineError::InvalidInstrument {
+                    pair: pair.to_string(),
+                    message: format!("{field} must be positive"),
+                });
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_amount, self.max_amount)
+            && min > max
+        {
+            return Err(EngineError::InvalidInstrument {
+                pair: pair.to_string(),
+                message: "min_amount is above max_amount".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Checks an order's amount, and its limit price if it has one.
+    pub(crate) fn check(
+        &self,
+        price: Option<Decimal>,
+        amount: Decimal,
+    ) -> Result<(), RejectReason> {
+        if let (Some(price), Some(tick)) = (price, self.tick_size)
+            && !(price % tick).is_zero()
+        {
+            return Err(RejectReason::PRICE_NOT_ON_TICK);
+        }
+        if self.lot_size.is_some_and(|lot| !(amount % lot).is_zero()) {
+            return Err(RejectReason::AMOUNT_NOT_ON_LOT);
+        }
+        if self.min_amount.is_some_and(|min| amount < min) {
+            return Err(RejectReason::AMOUNT_BELOW_MIN);
+        }
+        if self.max_amount.is_some_and(|max| amount > max) {
+            return Err(RejectReason::AMOUNT_ABOVE_MAX);
+        }
+        // A notional too large to represent is never below the minimum.
+        if let (Some(price), Some(min)) = (price, self.min_notional)
+            && price
+                .checked_mul(amount)
+                .is_some_and(|notional| notional < min)
+        {
+            return Err(RejectReason::NOTIONAL_BELOW_MIN);
+        }
+        Ok(())
+    }
+}
+
+/// Whether a listed pair takes orders. A halted pair still takes cancels;
+/// a delisted one takes nothing and can't come back.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TradingStatus {
+    #[default]
+    TRADING,
+    HALTED,
+    DELISTED,
+}
+
+/// What an operator can do to a listed pair.
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AdminAction {
+    HALT,
+    RESUME,
+    /// Cancels every resting order of the pair, then closes it for good.
+    DELIST,
+}
+
+/// One listed pair.
+#[derive(Serialize, Clone, Debug)]
+pub struct Instrument {
+    pair: String,
+    base: String,
+    quote: String,
+    pub(crate) status: TradingStatus,
+    rules: InstrumentRules,
+}
+
+impl Instrument {
+    /// Lists `pair`, which must read `BASE/QUOTE`, under `rules`.
+    pub fn new(pair: &str, rules: InstrumentRules) -> Result<Instrument, EngineError> {
+        let invalid = |message: &str| EngineError::InvalidInstrument {
+            pair: pair.to_string(),
+            message: message.to_string(),
+        };
+        let (base, quote) = pair
+            .split_once('/')
+            .filter(|(base, quote)| {
+                [base, quote].iter().all(|asset| {
+                    !asset.is_empty() && asset.chars().all(|c| c.is_ascii_alphanumeric())
+                })
+            })
+            .ok_or_else(|| invalid("pair is not BASE/QUOTE"))?;
+        rules.validate(pair)?;
+        Ok(Instrument {
+            pair: pair.to_string(),
+            base: base.to_string(),
+            quote: quote.to_string(),
+            status: TradingStatus::TRADING,
+            rules,
+        })
+    }
+
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    pub fn quote(&self) -> &str {
+        &self.quote
+    }
+
+    pub fn status(&self) -> TradingStatus {
+        self.status
+    }
+
+    pub fn rules(&self) -> &InstrumentRules {
+        &self.rules
+    }
+}
+
+/// The pairs an engine accepts orders for, by pair.
+#[derive(Clone, Debug, Default)]
+pub struct InstrumentRegistry {
+    instruments: BTreeMap<String, Instrument>,
+}
+
+impl InstrumentRegistry {
+    /// Reads a JSON object of rules keyed by pair, e.g.
+    /// `{"BTC/USDC": {"tick_size": "0.01", "lot_size": "0.0001"}, "ETH/USDC": {}}`.
+    /// Every pair in it is listed and trading.
+    pub fn load(path: impl AsRef<Path>) -> Result<InstrumentRegistry, EngineError> {
+        let rules: BTreeMap<String, InstrumentRules> =
+            serde_json::from_str(&fs::read_to_string(path)?)?;
+        let mut registry = InstrumentRegistry::default();
+        for (pair, rules) in rules {
+            registry.list(Instrument::new(&pair, rules)?);
+        }
+        Ok(registry)
+    }
+
+    /// Adds or replaces one instrument.
+    pub fn list(&mut self, instrument: Instrument) {
+        self.instruments.insert(instrument.pair.clone(), instrument);
+    }
+
+    pub fn get(&self, pair: &str) -> Option<&Instrument> {
+        self.instruments.get(pair)
+    }
+
+    pub(crate) fn get_mut(&mut self, pair: &str) -> Option<&mut Instrument> {
+        self.instruments.get_mut(pair)
+    }
+
+    /// Every instrument, by pair.
+    pub fn iter(&self) -> impl Iterator<Item = &Instrument> {
+        self.instruments.values()
+    }
+}
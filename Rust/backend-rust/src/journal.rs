@@ -0,0 +1,197 @@
+//! Write-ahead journal of the orders an engine ingests and the admin actions
+//! taken on its pairs. Each record is one line, `<crc32 hex> <json>`, synced
+//! to disk before it is applied, so replaying the file rebuilds the engine as
+//! it was.
+
+use crate::{AdminAction, EngineError, RawOrder};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// One journaled order or admin action, with the sequence number and clock
+/// stamp it was applied at.
+#[derive(Deserialize, Serialize)]
+pub struct JournalEntry {
+    pub(crate) seq: u64,
+    pub(crate) timestamp: u64,
+    #[serde(flatten)]
+    pub(crate) input: Journaled,
+}
+
+/// What a record applies, keyed `order` or `admin`.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum Journaled {
+    Order(RawOrder),
+    Admin { action: AdminAction, pair: String },
+}
+
+impl JournalEntry {
+    /// Reads every intact entry of the journal at `path` without changing
+    /// the file; a torn last record is skipped.
+    pub fn read_all(path: impl AsRef<Path>) -> Result<Vec<JournalEntry>, EngineError> {
+        let (entries, _) = parse(&fs::read(path)?)?;
+        Ok(entries)
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    /// The order, unless the entry is an admin action.
+    pub fn order(&self) -> Option<&RawOrder> {
+        match &self.input {
+            Journaled::Order(order) => Some(order),
+            Journaled::Admin { .. } => None,
+        }
+    }
+
+    /// The pair the order or admin action is for.
+    pub fn pair(&self) -> &str {
+        match &self.input {
+            Journaled::Order(order) => &order.pair,
+            Journaled::Admin { pair, .. } => pair,
+        }
+    }
+}
+
+/// `JournalEntry` as written, borrowing what it records.
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    seq: u64,
+    timestamp: u64,
+    #[serde(flatten)]
+    input: JournaledRef<'a>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum JournaledRef<'a> {
+    Order(&'a RawOrder),
+    Admin { action: AdminAction, pair: &'a str },
+}
+
+pub(crate) struct Journal {
+    file: File,
+    /// Length of the intact records, where the next one starts.
+    len: u64,
+    /// Set when a failed append left part of a record that couldn't be cut
+    /// off; appending after it would strand the torn record mid-file.
+    poisoned: bool,
+}
+
+impl Journal {
+    /// Opens or creates the journal at `path` and reads back every intact
+    /// record. A damaged last record is a torn append and is truncated away;
+    /// damage anywhere before it is an error.
+    pub(crate) fn open(path: &Path) -> Result<(Journal, Vec<JournalEntry>), EngineError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (entries, intact) = parse(&bytes)?;
+        if intact < bytes.len() {
+            file.set_len(intact as u64)?;
+            file.sync_data()?;
+        }
+        let journal = Journal {
+            file,
+            len: intact as u64,
+            poisoned: false,
+        };
+        Ok((journal, entries))
+    }
+
+    /// Appends one record and waits until it is on disk. If that fails, the
+    /// part of the record already written is cut off again, and if even
+    /// that fails the journal refuses every later append until reopened.
+    pub(crate) fn append(
+        &mut self,
+        seq: u64,
+        timestamp: u64,
+        input: JournaledRef,
+    ) -> Result<(), EngineError> {
+        let json = serde_json::to_string(&EntryRef {
+            seq,
+            timestamp,
+            input,
+        })?;
+        if self.poisoned {
+            return Err(EngineError::Io(io::Error::other(
+                "journal ends in a torn record; reopen it to recover",
+            )));
+        }
+        let checksum = crc32fast::hash(json.as_bytes());
+        let record = format!("{checksum:08x} {json}\n");
+        let written = self
+            .file
+            .write_all(record.as_bytes())
+            .and_then(|()| self.file.sync_data());
+        if let Err(err) = written {
+            let rolled_back = self
+                .file
+                .set_len(self.len)
+                .and_then(|()| self.file.sync_data());
+            self.poisoned = rolled_back.is_err();
+            return Err(err.into());
+        }
+        self.len += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Decodes the records in `bytes` and returns them with the length of the
+/// intact prefix they came from, which falls short of the end only when
+/// the last record is torn.
+fn parse(bytes: &[u8]) -> Result<(Vec<JournalEntry>, usize), EngineError> {
+    let mut entries = Vec::new();
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let rest = &bytes[offset..];
+        let record = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .and_then(|end| Some((decode(&rest[..end])?, end + 1)));
+        match record {
+            Some((entry, len)) => {
+                entries.push(entry);
+                offset += len;
+            }
+            None if is_last_line(rest) => break,
+            None => {
+                return Err(EngineError::CorruptJournal {
+                    offset: offset as u64,
+                });
+            }
+        }
+    }
+    Ok((entries, offset))
+}
+
+/// Checks and parses one record line, without its newline.
+fn decode(line: &[u8]) -> Option<JournalEntry> {
+    let line = std::str::from_utf8(line).ok()?;
+    let (checksum, json) = line.split_once(' ')?;
+    let checksum = u32::from_str_radix(checksum, 16).ok()?;
+    if crc32fast::hash(json.as_bytes()) != checksum {
+        return None;
+    }
+    serde_json::from_str(json).ok()
+}
+
+/// Whether nothing follows the first line of `rest`, counting a line that
+/// never got its newline.
+fn is_last_line(rest: &[u8]) -> bool {
+    match rest.iter().position(|&b| b == b'\n') {
+        Some(end) => end + 1 == rest.len(),
+        None => true,
+    }
+}
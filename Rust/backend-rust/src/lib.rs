@@ -0,0 +1,4698 @@
+//! Order matching engine: per-pair price-time priority books behind a
+//! `MatcherEngine` that sequences orders, trades and execution reports.
+
+mod instrument;
+mod journal;
+mod snapshot;
+
+pub use instrument::{AdminAction, Instrument, InstrumentRegistry, InstrumentRules, TradingStatus};
+pub use journal::JournalEntry;
+
+use journal::{Journal, Journaled, JournaledRef};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use snapshot::{BookSnapshot, EngineSnapshot, SNAPSHOT_VERSION};
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const BPS_PER_UNIT: Decimal = Decimal::from_parts(10_000, 0, 0, false, 0);
+const NANOS_PER_DAY: u64 = 86_400 * 1_000_000_000;
+
+#[derive(Deserialize, Serialize)]
+pub enum Operation {
+    CREATE,
+    DELETE,
+    MODIFY,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, Eq, PartialEq)]
+pub enum Side {
+    BUY,
+    SELL,
+}
+
+impl Side {
+    pub fn opposite(&self) -> Side {
+        match self {
+            Side::BUY => Side::SELL,
+            Side::SELL => Side::BUY,
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Eq, PartialEq)]
+pub enum OrderType {
+    #[default]
+    LIMIT,
+    MARKET,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Eq, PartialEq)]
+pub enum TimeInForce {
+    #[default]
+    GTC,
+    IOC,
+    FOK,
+    GTD,
+    DAY,
+}
+
+/// What a post-only order does when its price would take liquidity.
+#[derive(Deserialize, Serialize, Clone, Copy, Default, Eq, PartialEq)]
+pub enum CrossAction {
+    #[default]
+    REJECT,
+    SLIDE,
+}
+
+/// How a match between two orders of the same account is resolved.
+#[allow(non_camel_case_types)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum StpMode {
+    CANCEL_NEWEST,
+    CANCEL_OLDEST,
+    CANCEL_BOTH,
+    DECREMENT_AND_CANCEL,
+}
+
+impl TimeInForce {
+    fn rests(self) -> bool {
+        matches!(self, TimeInForce::GTC | TimeInForce::GTD | TimeInForce::DAY)
+    }
+}
+
+/// One input row: a create, cancel or modify request for a single order.
+#[derive(Deserialize, Serialize)]
+pub struct RawOrder {
+    pub type_op: Operation,
+    pub account_id: String,
+    pub amount: String,
+    pub order_id: String,
+    pub pair: String,
+    #[serde(default)]
+    pub limit_price: Option<String>,
+    pub side: Side,
+    #[serde(default)]
+    pub order_type: OrderType,
+    /// MARKET only: never trade buys above / sells below this price.
+    #[serde(default)]
+    pub worst_price: Option<String>,
+    /// MARKET only: maximum distance from the touch, in basis points.
+    #[serde(default)]
+    pub max_slippage_bps: Option<String>,
+    #[serde(default)]
+    pub time_in_force: TimeInForce,
+    /// GTD only: engine clock time (ns) at which the order expires.
+    #[serde(default)]
+    pub expire_time: Option<u64>,
+    /// Maker-only: the order rests or is handled per `on_cross`, never matched.
+    #[serde(default)]
+    pub post_only: bool,
+    #[serde(default)]
+    pub on_cross: CrossAction,
+    /// Overrides the engine's self-trade prevention mode for this order.
+    #[serde(default)]
+    pub stp_mode: Option<StpMode>,
+    /// Wall-clock time (ns) the order was received, read by `ReplayClock`.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Deserialize, Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum OrderStatus {
+    NEW,
+    PARTIALLY_FILLED,
+    FILLED,
+    CANCELLED,
+    EXPIRED,
+    REJECTED,
+}
+
+#[derive(Deserialize, Serialize, Clone, Eq, PartialEq)]
+struct BookOrder {
+    id: String,
+    account: String,
+    side: Side,
+    pair: String,
+    order_type: OrderType,
+    price: Decimal,
+    quantity: Decimal,
+    remaining: Decimal,
+    filled: Decimal,
+    /// Sum of price * quantity over all fills, for the average fill price.
+    filled_notional: Decimal,
+    status: OrderStatus,
+    ts: u64,
+    /// Wall-clock time (ns) of the input that last sequenced the order.
+    timestamp: u64,
+    time_in_force: TimeInForce,
+    expires_at: Option<u64>,
+    stp_mode: Option<StpMode>,
+    /// Kept so an amend that re-prices or upsizes the order is placed as
+    /// maker-only again.
+    #[serde(default)]
+    post_only: bool,
+    #[serde(default)]
+    on_cross: CrossAction,
+}
+
+impl BookOrder {
+    fn record_fill(&mut self, qty: Decimal, price: Decimal) {
+        self.remaining -= qty;
+        self.filled += qty;
+        // `check_notional` bounded every fill's notional on entry.
+        self.filled_notional = qty
+            .checked_mul(price)
+            .and_then(|notional| self.filled_notional.checked_add(notional))
+            .unwrap_or(Decimal::MAX);
+        self.status = self.open_status();
+    }
+
+    /// Status of an order that is still working: FILLED once nothing is left.
+    fn open_status(&self) -> OrderStatus {
+        if self.remaining <= Decimal::ZERO {
+            OrderStatus::FILLED
+        } else if self.filled > Decimal::ZERO {
+            OrderStatus::PARTIALLY_FILLED
+        } else {
+            OrderStatus::NEW
+        }
+    }
+
+    fn limit_price(&self) -> Option<String> {
+        matches!(self.order_type, OrderType::LIMIT).then(|| self.price.to_string())
+    }
+
+    /// Execution report for the order as it stands, with the fill that caused
+    /// it, if any, as `(price, qty)`. `ts` is stamped when it is emitted.
+    fn report(
+        &self,
+        exec_type: ExecType,
+        last_fill: Option<(Decimal, Decimal)>,
+        reason: Option<ReasonCode>,
+    ) -> ExecutionReport {
+        ExecutionReport {
+            pair: self.pair.clone(),
+            order_id: self.id.clone(),
+            exec_type,
+            status: self.status,
+            price: self.limit_price(),
+            leaves_qty: self.remaining.to_string(),
+            cum_qty: self.filled.to_string(),
+            last_price: last_fill.map(|(price, _)| price.to_string()),
+            last_qty: last_fill.map(|(_, qty)| qty.to_string()),
+            reason,
+            ts: 0,
+        }
+    }
+
+    fn state(&self) -> OrderState {
+        OrderState {
+            order_id: self.id.clone(),
+            pair: self.pair.clone(),
+            side: self.side.clone(),
+            status: self.status,
+            price: self.limit_price(),
+            quantity: self.quantity.to_string(),
+            filled: self.filled.to_string(),
+            remaining: self.remaining.to_string(),
+            avg_price: (self.filled > Decimal::ZERO)
+                .then(|| (self.filled_notional / self.filled).normalize().to_string()),
+        }
+    }
+}
+
+/// Point-in-time view of an order, live or terminal.
+#[derive(Serialize, Clone, Debug)]
+pub struct OrderState {
+    #[serde(rename = "orderId")]
+    order_id: String,
+    pair: String,
+    side: Side,
+    status: OrderStatus,
+    price: Option<String>,
+    quantity: String,
+    filled: String,
+    remaining: String,
+    #[serde(rename = "avgPrice")]
+    avg_price: Option<String>,
+}
+
+impl OrderState {
+    pub fn order_id(&self) -> &str {
+        &self.order_id
+    }
+
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    pub fn side(&self) -> Side {
+        self.side.clone()
+    }
+
+    pub fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    /// Limit price; `None` for MARKET orders.
+    pub fn price(&self) -> Option<&str> {
+        self.price.as_deref()
+    }
+
+    pub fn quantity(&self) -> &str {
+        &self.quantity
+    }
+
+    pub fn filled(&self) -> &str {
+        &self.filled
+    }
+
+    pub fn remaining(&self) -> &str {
+        &self.remaining
+    }
+
+    /// Volume-weighted fill price; `None` until the first fill.
+    pub fn avg_price(&self) -> Option<&str> {
+        self.avg_price.as_deref()
+    }
+}
+
+/// CSV header of `Trade` rows.
+pub const TRADE_HEADER: [&str; 8] = [
+    "tradeId",
+    "pair",
+    "buyOrderId",
+    "sellOrderId",
+    "price",
+    "amount",
+    "ts",
+    "timestamp",
+];
+
+#[derive(Serialize, Clone)]
+pub struct Trade {
+    #[serde(rename = "tradeId")]
+    trade_id: u64,
+    pair: String,
+    #[serde(rename = "buyOrderId")]
+    buy_order_id: String,
+    #[serde(rename = "sellOrderId")]
+    sell_order_id: String,
+    price: String,
+    amount: String,
+    ts: u64,
+    timestamp: u64,
+}
+
+impl Trade {
+    pub fn trade_id(&self) -> u64 {
+        self.trade_id
+    }
+
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    pub fn buy_order_id(&self) -> &str {
+        &self.buy_order_id
+    }
+
+    pub fn sell_order_id(&self) -> &str {
+        &self.sell_order_id
+    }
+
+    pub fn price(&self) -> &str {
+        &self.price
+    }
+
+    pub fn amount(&self) -> &str {
+        &self.amount
+    }
+
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
+    /// Wall-clock time (ns) of the order that took liquidity.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExecType {
+    ACK,
+    REPLACE,
+    RESTATED,
+    PARTIAL_FILL,
+    FILL,
+    CANCEL,
+    REJECT,
+    EXPIRE,
+}
+
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ReasonCode {
+    USER_CANCEL,
+    UNFILLED_REMAINDER,
+    FOK_NOT_FILLABLE,
+    POST_ONLY_REPRICED,
+    POST_ONLY_WOULD_CROSS,
+    POST_ONLY_NO_VALID_PRICE,
+    EXPIRED_ON_ENTRY,
+    TIME_EXPIRED,
+    SELF_TRADE_PREVENTED,
+    INSTRUMENT_DELISTED,
+    #[serde(untagged)]
+    REJECTED(RejectReason),
+}
+
+/// Why an input row was refused before it could touch a book.
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RejectReason {
+    MALFORMED_ORDER,
+    INVALID_AMOUNT,
+    INVALID_PRICE,
+    INVALID_SLIPPAGE,
+    MISSING_LIMIT_PRICE,
+    MISSING_EXPIRE_TIME,
+    UNKNOWN_PAIR,
+    DUPLICATE_ORDER_ID,
+    UNKNOWN_ORDER,
+    PRICE_NOT_ON_TICK,
+    AMOUNT_NOT_ON_LOT,
+    AMOUNT_BELOW_MIN,
+    AMOUNT_ABOVE_MAX,
+    NOTIONAL_BELOW_MIN,
+    PAIR_HALTED,
+    PAIR_DELISTED,
+    SLIDE_WITHOUT_TICK,
+    AMOUNT_OVERFLOW,
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            RejectReason::MALFORMED_ORDER => "malformed order row",
+            RejectReason::INVALID_AMOUNT => "amount is not a positive decimal",
+            RejectReason::INVALID_PRICE => "price is not a positive decimal",
+            RejectReason::INVALID_SLIPPAGE => "max_slippage_bps is not a non-negative decimal",
+            RejectReason::MISSING_LIMIT_PRICE => "LIMIT order without limit_price",
+            RejectReason::MISSING_EXPIRE_TIME => "GTD order without expire_time",
+            RejectReason::UNKNOWN_PAIR => "unknown pair",
+            RejectReason::DUPLICATE_ORDER_ID => "duplicate order_id",
+            RejectReason::UNKNOWN_ORDER => "no live order with this order_id",
+            RejectReason::PRICE_NOT_ON_TICK => "price is not a multiple of the tick size",
+            RejectReason::AMOUNT_NOT_ON_LOT => "amount is not a multiple of the lot size",
+            RejectReason::AMOUNT_BELOW_MIN => "amount is below the pair's minimum",
+            RejectReason::AMOUNT_ABOVE_MAX => "amount is above the pair's maximum",
+            RejectReason::NOTIONAL_BELOW_MIN => {
+                "price * amount is below the pair's minimum notional"
+            }
+            RejectReason::PAIR_HALTED => "pair is halted",
+            RejectReason::PAIR_DELISTED => "pair is delisted",
+            RejectReason::SLIDE_WITHOUT_TICK => "on_cross SLIDE needs a tick_size for the pair",
+            RejectReason::AMOUNT_OVERFLOW => "price * amount is too large to represent",
+        };
+        f.write_str(message)
+    }
+}
+
+#[derive(Debug)]
+pub enum EngineError {
+    /// A single order was refused; the rest of the batch carries on.
+    Rejected {
+        order_id: String,
+        reason: RejectReason,
+    },
+    Io(io::Error),
+    Json(serde_json::Error),
+    Csv(csv::Error),
+    /// A journal record that is damaged but not the last one, so it can't
+    /// be a torn append and isn't safe to drop.
+    CorruptJournal {
+        offset: u64,
+    },
+    UnsupportedSnapshot {
+        version: u64,
+    },
+    /// An instrument config entry or admin action that can't be applied.
+    InvalidInstrument {
+        pair: String,
+        message: String,
+    },
+    /// An admin action on a pair that isn't listed.
+    UnknownInstrument {
+        pair: String,
+    },
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Rejected { order_id, reason } => {
+                write!(f, "order {order_id} rejected: {reason}")
+            }
+            EngineError::Io(err) => write!(f, "I/O error: {err}"),
+            EngineError::Json(err) => write!(f, "JSON error: {err}"),
+            EngineError::Csv(err) => write!(f, "CSV error: {err}"),
+            EngineError::CorruptJournal { offset } => {
+                write!(f, "journal record at byte {offset} is corrupt")
+            }
+            EngineError::UnsupportedSnapshot { version } => {
+                write!(f, "snapshot format version {version} is not supported")
+            }
+            EngineError::InvalidInstrument { pair, message } => {
+                write!(f, "instrument {pair}: {message}")
+            }
+            EngineError::UnknownInstrument { pair } => write!(f, "{pair} is not listed"),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+impl From<io::Error> for EngineError {
+    fn from(err: io::Error) -> Self {
+        EngineError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for EngineError {
+    fn from(err: serde_json::Error) -> Self {
+        EngineError::Json(err)
+    }
+}
+
+impl From<csv::Error> for EngineError {
+    fn from(err: csv::Error) -> Self {
+        EngineError::Csv(err)
+    }
+}
+
+fn parse_positive(value: &str, reason: RejectReason) -> Result<Decimal, RejectReason> {
+    match Decimal::from_str(value) {
+        Ok(decimal) if decimal > Decimal::ZERO => Ok(decimal),
+        _ => Err(reason),
+    }
+}
+
+/// One state change of one order, emitted next to the `Trade` stream.
+#[derive(Serialize, Clone)]
+pub struct ExecutionReport {
+    pair: String,
+    #[serde(rename = "orderId")]
+    order_id: String,
+    #[serde(rename = "execType")]
+    exec_type: ExecType,
+    status: OrderStatus,
+    price: Option<String>,
+    #[serde(rename = "leavesQty")]
+    leaves_qty: String,
+    #[serde(rename = "cumQty")]
+    cum_qty: String,
+    #[serde(rename = "lastPrice")]
+    last_price: Option<String>,
+    #[serde(rename = "lastQty")]
+    last_qty: Option<String>,
+    reason: Option<ReasonCode>,
+    ts: u64,
+}
+
+impl ExecutionReport {
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    pub fn order_id(&self) -> &str {
+        &self.order_id
+    }
+
+    pub fn exec_type(&self) -> ExecType {
+        self.exec_type
+    }
+
+    pub fn status(&self) -> OrderStatus {
+        self.status
+    }
+
+    pub fn price(&self) -> Option<&str> {
+        self.price.as_deref()
+    }
+
+    pub fn leaves_qty(&self) -> &str {
+        &self.leaves_qty
+    }
+
+    pub fn cum_qty(&self) -> &str {
+        &self.cum_qty
+    }
+
+    pub fn last_price(&self) -> Option<&str> {
+        self.last_price.as_deref()
+    }
+
+    pub fn last_qty(&self) -> Option<&str> {
+        self.last_qty.as_deref()
+    }
+
+    pub fn reason(&self) -> Option<ReasonCode> {
+        self.reason
+    }
+
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
+    fn rejected(pair: &str, order_id: &str, price: Option<String>, reason: RejectReason) -> Self {
+        ExecutionReport {
+            pair: pair.to_string(),
+            order_id: order_id.to_string(),
+            exec_type: ExecType::REJECT,
+            status: OrderStatus::REJECTED,
+            price,
+            leaves_qty: Decimal::ZERO.to_string(),
+            cum_qty: Decimal::ZERO.to_string(),
+            last_price: None,
+            last_qty: None,
+            reason: Some(ReasonCode::REJECTED(reason)),
+            ts: 0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct Order {
+    pair: String,
+    bids: Vec<Bid>,
+    asks: Vec<Ask>,
+}
+
+#[derive(Serialize)]
+pub struct Bid {
+    id: String,
+    price: String,
+    remaining: String,
+    account: String,
+    ts: u64,
+    timestamp: u64,
+}
+
+impl Bid {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn price(&self) -> &str {
+        &self.price
+    }
+
+    pub fn remaining(&self) -> &str {
+        &self.remaining
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    /// Sequence number that set the order's time priority.
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
+    /// Wall-clock time (ns) that goes with `ts`.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+#[derive(Serialize)]
+pub struct Ask {
+    id: String,
+    price: String,
+    remaining: String,
+    account: String,
+    ts: u64,
+    timestamp: u64,
+}
+
+impl Ask {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn price(&self) -> &str {
+        &self.price
+    }
+
+    pub fn remaining(&self) -> &str {
+        &self.remaining
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    /// Sequence number that set the order's time priority.
+    pub fn ts(&self) -> u64 {
+        self.ts
+    }
+
+    /// Wall-clock time (ns) that goes with `ts`.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+}
+
+/// CSV header of `BookRow` rows.
+pub const BOOK_ROW_HEADER: [&str; 8] = [
+    "pair",
+    "side",
+    "id",
+    "price",
+    "remaining",
+    "account",
+    "ts",
+    "timestamp",
+];
+
+/// One resting order of an `Order` snapshot, flattened for CSV.
+#[derive(Serialize)]
+pub struct BookRow<'a> {
+    pair: &'a str,
+    side: Side,
+    id: &'a str,
+    price: &'a str,
+    remaining: &'a str,
+    account: &'a str,
+    ts: u64,
+    timestamp: u64,
+}
+
+impl Order {
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    /// Resting buys, best price first, then by time.
+    pub fn bids(&self) -> &[Bid] {
+        &self.bids
+    }
+
+    /// Resting sells, best price first, then by time.
+    pub fn asks(&self) -> &[Ask] {
+        &self.asks
+    }
+
+    /// Bids best first, then asks best first.
+    pub fn rows(&self) -> impl Iterator<Item = BookRow<'_>> {
+        let bids = self.bids.iter().map(|b| BookRow {
+            pair: &self.pair,
+            side: Side::BUY,
+            id: &b.id,
+            price: &b.price,
+            remaining: &b.remaining,
+            account: &b.account,
+            ts: b.ts,
+            timestamp: b.timestamp,
+        });
+        let asks = self.asks.iter().map(|a| BookRow {
+            pair: &self.pair,
+            side: Side::SELL,
+            id: &a.id,
+            price: &a.price,
+            remaining: &a.remaining,
+            account: &a.account,
+            ts: a.ts,
+            timestamp: a.timestamp,
+        });
+        bids.chain(asks)
+    }
+}
+
+/// Aggregate of the orders resting at one price.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Level {
+    price: String,
+    quantity: String,
+    orders: usize,
+}
+
+impl Level {
+    pub fn price(&self) -> &str {
+        &self.price
+    }
+
+    /// Total remaining quantity; "0" in an update means the level is gone.
+    pub fn quantity(&self) -> &str {
+        &self.quantity
+    }
+
+    pub fn orders(&self) -> usize {
+        self.orders
+    }
+}
+
+/// Level-2 view of one book, best price first on each side. As a snapshot
+/// it holds every level, as a `depth` view the best (possibly grouped) ones;
+/// as an update only the levels that changed, which replace what the client
+/// has at those prices.
+#[derive(Serialize, Clone, Debug)]
+pub struct DepthUpdate {
+    pair: String,
+    /// Per-book counter of updates; a snapshot carries the last one it
+    /// includes, so later updates apply from `seq + 1` without gaps.
+    seq: u64,
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+}
+
+impl DepthUpdate {
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn bids(&self) -> &[Level] {
+        &self.bids
+    }
+
+    pub fn asks(&self) -> &[Level] {
+        &self.asks
+    }
+}
+
+/// How much a simulated sweep takes: a base `Amount`, or a `Notional` in
+/// the quote currency.
+#[derive(Clone, Copy, Debug)]
+pub enum SweepSize {
+    Amount(Decimal),
+    Notional(Decimal),
+}
+
+/// What an order of a given size would trade against the book right now.
+#[derive(Serialize, Clone, Debug)]
+pub struct SweepEstimate {
+    side: Side,
+    /// Base quantity that would trade.
+    filled: String,
+    /// Quote quantity that would change hands.
+    notional: String,
+    #[serde(rename = "avgPrice")]
+    avg_price: Option<String>,
+    #[serde(rename = "worstPrice")]
+    worst_price: Option<String>,
+    #[serde(rename = "levelsConsumed")]
+    levels_consumed: usize,
+    /// What the book can't cover, in the unit the size was given in.
+    unfilled: String,
+}
+
+impl SweepEstimate {
+    pub fn side(&self) -> Side {
+        self.side.clone()
+    }
+
+    pub fn filled(&self) -> &str {
+        &self.filled
+    }
+
+    pub fn notional(&self) -> &str {
+        &self.notional
+    }
+
+    /// Volume-weighted price; `None` if nothing would trade.
+    pub fn avg_price(&self) -> Option<&str> {
+        self.avg_price.as_deref()
+    }
+
+    /// Price of the last level reached; `None` if nothing would trade.
+    pub fn worst_price(&self) -> Option<&str> {
+        self.worst_price.as_deref()
+    }
+
+    /// Levels reached, including a last one taken only in part.
+    pub fn levels_consumed(&self) -> usize {
+        self.levels_consumed
+    }
+
+    pub fn unfilled(&self) -> &str {
+        &self.unfilled
+    }
+}
+
+/// Engine-wide counters. Every accepted order, trade and execution report
+/// takes the next `seq`, so streams from all books merge into one gapless
+/// sequence; trades also take the next `trade_id`.
+#[derive(Clone, Copy)]
+struct Sequencer {
+    seq: u64,
+    trade_id: u64,
+}
+
+impl Sequencer {
+    fn new() -> Self {
+        Sequencer {
+            seq: 1,
+            trade_id: 1,
+        }
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.seq;
+        self.seq += 1;
+        seq
+    }
+
+    fn next_trade_id(&mut self) -> u64 {
+        let trade_id = self.trade_id;
+        self.trade_id += 1;
+        trade_id
+    }
+}
+
+/// Source of the wall-clock time (ns) stamped on each input next to its
+/// logical sequence number.
+pub trait Clock: Send {
+    fn stamp(&mut self, raw: &RawOrder) -> u64;
+}
+
+/// Reads the host clock, for live use.
+pub struct SystemClock;
+
+impl SystemClock {
+    fn unix_nanos() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("System clock before UNIX epoch")
+            .as_nanos() as u64
+    }
+}
+
+impl Clock for SystemClock {
+    fn stamp(&mut self, _raw: &RawOrder) -> u64 {
+        Self::unix_nanos()
+    }
+}
+
+/// Replays the `timestamp` recorded on each order, so reruns are identical.
+/// Orders without one reuse the last time seen.
+#[derive(Default)]
+pub struct ReplayClock {
+    last: u64,
+}
+
+impl Clock for ReplayClock {
+    fn stamp(&mut self, raw: &RawOrder) -> u64 {
+        if let Some(timestamp) = raw.timestamp {
+            self.last = timestamp;
+        }
+        self.last
+    }
+}
+
+/// Arena slot of a resting order. Orders at one price are chained through
+/// `prev`/`next` handles, so an order can be unlinked in O(1) by its handle.
+struct OrderNode {
+    order: BookOrder,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// FIFO queue of the orders resting at a single price.
+#[derive(Default)]
+struct PriceLevel {
+    head: Option<usize>,
+    tail: Option<usize>,
+    quantity: Decimal,
+    orders: usize,
+}
+
+/// Price-time priority book for one pair.
+pub struct OrderBook {
+    pair: String,
+    bids: BTreeMap<Decimal, PriceLevel>,
+    asks: BTreeMap<Decimal, PriceLevel>,
+    orders: Vec<Option<OrderNode>>,
+    free_slots: Vec<usize>,
+    id_index: HashMap<String, usize>,
+    /// Ids of resting GTD/DAY orders by expiry time, so the clock only has
+    /// to look at the ones that are due.
+    expiries: BTreeMap<u64, Vec<String>>,
+    /// Default for orders that don't carry their own `stp_mode`.
+    stp_mode: Option<StpMode>,
+    /// Orders that reached a terminal status, kept for status queries and
+    /// duplicate id checks.
+    closed: HashMap<String, BookOrder>,
+    /// Ids in `closed`, oldest close first.
+    closed_ids: VecDeque<String>,
+    /// How many closed orders to keep; the oldest are forgotten past it.
+    closed_retention: Option<usize>,
+    sequencer: Sequencer,
+    now: u64,
+    /// Wall-clock time (ns) of the input being processed.
+    timestamp: u64,
+    rules: InstrumentRules,
+    trades: Vec<Trade>,
+    reports: Vec<ExecutionReport>,
+    /// Prices whose level changed since the last depth update, per side.
+    dirty_bids: BTreeSet<Decimal>,
+    dirty_asks: BTreeSet<Decimal>,
+    depth_seq: u64,
+}
+
+impl OrderBook {
+    pub fn new(pair: String) -> Self {
+        OrderBook {
+            pair,
+            bids: BTreeMap::new(),
+            asks: BTreeMap::new(),
+            orders: Vec::new(),
+            free_slots: Vec::new(),
+            id_index: HashMap::new(),
+            expiries: BTreeMap::new(),
+            stp_mode: None,
+            closed: HashMap::new(),
+            closed_ids: VecDeque::new(),
+            closed_retention: None,
+            sequencer: Sequencer::new(),
+            now: 0,
+            timestamp: 0,
+            rules: InstrumentRules::default(),
+            trades: Vec::new(),
+            reports: Vec::new(),
+            dirty_bids: BTreeSet::new(),
+            dirty_asks: BTreeSet::new(),
+            depth_seq: 0,
+        }
+    }
+
+    /// Rules new and amended orders are checked against from now on; orders
+    /// already resting are left as they are.
+    pub fn set_rules(&mut self, rules: InstrumentRules) {
+        self.rules = rules;
+    }
+
+    pub fn rules(&self) -> &InstrumentRules {
+        &self.rules
+    }
+
+    /// Applies one input row. A row that fails validation is rejected with an
+    /// execution report and an error; the book itself is left untouched.
+    pub fn process(&mut self, raw: RawOrder) -> Result<(), EngineError> {
+        let result = match raw.type_op {
+            Operation::CREATE => self.create(&raw),
+            Operation::DELETE => self.cancel(&raw.order_id),
+            Operation::MODIFY => self.modify(&raw),
+        };
+        result.map_err(|reason| {
+            self.emit(ExecutionReport::rejected(
+                &raw.pair,
+                &raw.order_id,
+                raw.limit_price.clone(),
+                reason,
+            ));
+            EngineError::Rejected {
+                order_id: raw.order_id,
+                reason,
+            }
+        })
+    }
+
+    /// Stamps a report with the next sequence number and queues it.
+    fn emit(&mut self, mut report: ExecutionReport) {
+        report.ts = self.sequencer.next_seq();
+        self.reports.push(report);
+    }
+
+    fn cancel(&mut self, order_id: &str) -> Result<(), RejectReason> {
+        let order = self.remove(order_id).ok_or(RejectReason::UNKNOWN_ORDER)?;
+        self.close(order, OrderStatus::CANCELLED, Some(ReasonCode::USER_CANCEL));
+        Ok(())
+    }
+
+    fn knows(&self, order_id: &str) -> bool {
+        self.id_index.contains_key(order_id) || self.closed.contains_key(order_id)
+    }
+
+    fn create(&mut self, raw: &RawOrder) -> Result<(), RejectReason> {
+        if self.knows(&raw.order_id) {
+            return Err(RejectReason::DUPLICATE_ORDER_ID);
+        }
+        let amount = parse_positive(&raw.amount, RejectReason::INVALID_AMOUNT)?;
+        let order_type = raw.order_type;
+        let price = match order_type {
+            OrderType::LIMIT => {
+                let limit_price = raw
+                    .limit_price
+                    .as_deref()
+                    .ok_or(RejectReason::MISSING_LIMIT_PRICE)?;
+                parse_positive(limit_price, RejectReason::INVALID_PRICE)?
+            }
+            OrderType::MARKET => {
+                let worst_price = raw
+                    .worst_price
+                    .as_deref()
+                    .map(|p| parse_positive(p, RejectReason::INVALID_PRICE))
+                    .transpose()?;
+                let max_slippage_bps = raw
+                    .max_slippage_bps
+                    .as_deref()
+                    .map(|b| match Decimal::from_str(b) {
+                        Ok(bps) if bps >= Decimal::ZERO => Ok(bps),
+                        _ => Err(RejectReason::INVALID_SLIPPAGE),
+                    })
+                    .transpose()?;
+                self.market_price_limit(&raw.side, worst_price, max_slippage_bps)?
+            }
+        };
+        let limit_price = matches!(order_type, OrderType::LIMIT).then_some(price);
+        self.rules.check(limit_price, amount)?;
+        self.check_notional(&raw.side, order_type, price, amount, Decimal::ZERO)?;
+        if limit_price.is_some() {
+            self.check_slide(raw.post_only, raw.on_cross)?;
+        }
+        let time_in_force = raw.time_in_force;
+        let expires_at = match time_in_force {
+            TimeInForce::GTD => Some(raw.expire_time.ok_or(RejectReason::MISSING_EXPIRE_TIME)?),
+            // The last day the clock can count to ends at the end of time.
+            TimeInForce::DAY => Some((self.now / NANOS_PER_DAY + 1).saturating_mul(NANOS_PER_DAY)),
+            _ => None,
+        };
+        let order = BookOrder {
+            id: raw.order_id.clone(),
+            account: raw.account_id.clone(),
+            side: raw.side.clone(),
+            pair: raw.pair.clone(),
+            order_type,
+            price,
+            quantity: amount,
+            remaining: amount,
+            filled: Decimal::ZERO,
+            filled_notional: Decimal::ZERO,
+            status: OrderStatus::NEW,
+            ts: self.sequencer.next_seq(),
+            timestamp: self.timestamp,
+            time_in_force,
+            expires_at,
+            stp_mode: raw.stp_mode,
+            post_only: raw.post_only,
+            on_cross: raw.on_cross,
+        };
+        if expires_at.is_some_and(|t| t <= self.now) {
+            self.close(
+                order,
+                OrderStatus::REJECTED,
+                Some(ReasonCode::EXPIRED_ON_ENTRY),
+            );
+            return Ok(());
+        }
+        if order.post_only {
+            self.place_post_only(order, ExecType::ACK);
+            return Ok(());
+        }
+        self.emit(order.report(ExecType::ACK, None, None));
+        if time_in_force == TimeInForce::FOK && !self.can_fill(&order) {
+            self.close(
+                order,
+                OrderStatus::CANCELLED,
+                Some(ReasonCode::FOK_NOT_FILLABLE),
+            );
+            return Ok(());
+        }
+        self.match_then_rest(order);
+        Ok(())
+    }
+
+    fn match_then_rest(&mut self, mut order: BookOrder) {
+        if self.match_order(&mut order) {
+            self.close(
+                order,
+                OrderStatus::CANCELLED,
+                Some(ReasonCode::SELF_TRADE_PREVENTED),
+            );
+        } else {
+            self.rest_or_close(order);
+        }
+    }
+
+    /// Rests whatever is left of an order after matching. MARKET, IOC and FOK
+    /// remainders are cancelled, never rested.
+    fn rest_or_close(&mut self, order: BookOrder) {
+        if order.remaining <= Decimal::ZERO {
+            self.close(order, OrderStatus::FILLED, None);
+        } else if matches!(order.order_type, OrderType::LIMIT) && order.time_in_force.rests() {
+            self.add(order);
+        } else {
+            self.close(
+                order,
+                OrderStatus::CANCELLED,
+                Some(ReasonCode::UNFILLED_REMAINDER),
+            );
+        }
+    }
+
+    /// Moves an order to its terminal status. Anything still open is gone,
+    /// so leaves drop to zero; FILLED was already reported with its fill.
+    fn close(&mut self, mut order: BookOrder, status: OrderStatus, reason: Option<ReasonCode>) {
+        order.status = status;
+        let exec_type = match status {
+            OrderStatus::CANCELLED => Some(ExecType::CANCEL),
+            OrderStatus::EXPIRED => Some(ExecType::EXPIRE),
+            OrderStatus::REJECTED => Some(ExecType::REJECT),
+            _ => None,
+        };
+        if let Some(exec_type) = exec_type {
+            order.remaining = Decimal::ZERO;
+            self.emit(order.report(exec_type, None, reason));
+        }
+        let id = order.id.clone();
+        if self.closed.insert(id.clone(), order).is_none() {
+            self.closed_ids.push_back(id);
+        }
+        self.forget_closed();
+    }
+
+    /// Drops the oldest closed orders beyond `closed_retention`.
+    fn forget_closed(&mut self) {
+        let retention = self.closed_retention.unwrap_or(usize::MAX);
+        while self.closed_ids.len() > retention {
+            if let Some(id) = self.closed_ids.pop_front() {
+                self.closed.remove(&id);
+            }
+        }
+    }
+
+    pub fn order_status(&self, order_id: &str) -> Option<OrderState> {
+        self.id_index
+            .get(order_id)
+            .map(|&handle| &self.node(handle).order)
+            .or_else(|| self.closed.get(order_id))
+            .map(BookOrder::state)
+    }
+
+    /// Amends a resting order to a new open `amount` and optional new price.
+    /// A pure size reduction keeps the order's `ts` priority; any increase or
+    /// price change re-sequences it and matches it again if it now crosses,
+    /// or, for a post-only order, places it again as maker-only.
+    fn modify(&mut self, raw: &RawOrder) -> Result<(), RejectReason> {
+        let &handle = self
+            .id_index
+            .get(&raw.order_id)
+            .ok_or(RejectReason::UNKNOWN_ORDER)?;
+        let existing = self.node(handle).order.clone();
+        let amount = parse_positive(&raw.amount, RejectReason::INVALID_AMOUNT)?;
+        let price = match raw.limit_price.as_deref() {
+            Some(p) => parse_positive(p, RejectReason::INVALID_PRICE)?,
+            None => existing.price,
+        };
+        self.rules.check(Some(price), amount)?;
+        self.check_notional(
+            &existing.side,
+            existing.order_type,
+            price,
+            amount,
+            existing.filled_notional,
+        )?;
+        self.check_slide(existing.post_only, existing.on_cross)?;
+        if price == existing.price && amount <= existing.remaining {
+            let reduction = existing.remaining - amount;
+            self.node_mut(handle).order.quantity -= reduction;
+            self.reduce_resting(handle, reduction);
+            let report = self
+                .node(handle)
+                .order
+                .report(ExecType::REPLACE, None, None);
+            self.emit(report);
+            return Ok(());
+        }
+
+        self.remove(&existing.id);
+        let order = BookOrder {
+            price,
+            quantity: existing.filled + amount,
+            remaining: amount,
+            ts: self.sequencer.next_seq(),
+            timestamp: self.timestamp,
+            ..existing
+        };
+        if order.post_only {
+            self.place_post_only(order, ExecType::REPLACE);
+            return Ok(());
+        }
+        self.emit(order.report(ExecType::REPLACE, None, None));
+        self.match_then_rest(order);
+        Ok(())
+    }
+
+    /// Rejects an order whose fills could add up to more notional than a
+    /// `Decimal` holds, on top of the `filled` notional it already has. It
+    /// fills at no worse than its own limit, or for a SELL at the best bid,
+    /// and a resting remainder is later filled at its limit and adds to its
+    /// level's total.
+    fn check_notional(
+        &self,
+        side: &Side,
+        order_type: OrderType,
+        price: Decimal,
+        amount: Decimal,
+        filled: Decimal,
+    ) -> Result<(), RejectReason> {
+        let resting = match order_type {
+            OrderType::LIMIT => price,
+            OrderType::MARKET => Decimal::ZERO,
+        };
+        let level = match side {
+            Side::BUY => self.bids.get(&price),
+            Side::SELL => self.asks.get(&price),
+        };
+        if level.is_some_and(|level| level.quantity.checked_add(amount).is_none()) {
+            return Err(RejectReason::AMOUNT_OVERFLOW);
+        }
+        let worst = match side {
+            Side::BUY => self
+                .asks
+                .last_key_value()
+                .map_or(Decimal::ZERO, |(&ask, _)| ask.min(price)),
+            Side::SELL => self.best_bid().unwrap_or(Decimal::ZERO),
+        };
+        amount
+            .checked_mul(worst.max(resting))
+            .and_then(|notional| notional.checked_add(filled))
+            .map(|_| ())
+            .ok_or(RejectReason::AMOUNT_OVERFLOW)
+    }
+
+    /// A post-only order that slides when it would cross needs a tick size
+    /// to slide by.
+    fn check_slide(&self, post_only: bool, on_cross: CrossAction) -> Result<(), RejectReason> {
+        if post_only && on_cross == CrossAction::SLIDE && self.rules.tick_size.is_none() {
+            return Err(RejectReason::SLIDE_WITHOUT_TICK);
+        }
+        Ok(())
+    }
+
+    /// Rests a maker-only order without ever calling `match_order`. A price
+    /// that would cross the touch is rejected or slid one tick behind it,
+    /// which takes the pair's configured tick size. A resting order is
+    /// reported as `accepted`: ACK when new, REPLACE when amended.
+    fn place_post_only(&mut self, mut order: BookOrder, accepted: ExecType) {
+        let touch = match order.side {
+            Side::BUY => self.best_ask(),
+            Side::SELL => self.best_bid(),
+        };
+        let crosses = matches!(order.order_type, OrderType::MARKET)
+            || touch.is_some_and(|t| match order.side {
+                Side::BUY => order.price >= t,
+                Side::SELL => order.price <= t,
+            });
+        if !crosses {
+            self.emit(order.report(accepted, None, None));
+            self.rest_or_close(order);
+            return;
+        }
+        match (
+            order.on_cross,
+            order.order_type,
+            touch,
+            self.rules.tick_size,
+        ) {
+            (CrossAction::SLIDE, OrderType::LIMIT, Some(touch), Some(tick)) => {
+                order.price = match order.side {
+                    Side::BUY => touch - tick,
+                    Side::SELL => touch + tick,
+                };
+                if order.price <= Decimal::ZERO {
+                    self.close(
+                        order,
+                        OrderStatus::REJECTED,
+                        Some(ReasonCode::POST_ONLY_NO_VALID_PRICE),
+                    );
+                    return;
+                }
+                self.emit(order.report(accepted, None, Some(ReasonCode::POST_ONLY_REPRICED)));
+                self.rest_or_close(order);
+            }
+            _ => self.close(
+                order,
+                OrderStatus::REJECTED,
+                Some(ReasonCode::POST_ONLY_WOULD_CROSS),
+            ),
+        }
+    }
+
+    /// Whether the resting liquidity the incoming order could trade against
+    /// covers its whole size. Under self-trade prevention its own account's
+    /// orders never trade with it, and under CANCEL_NEWEST or CANCEL_BOTH
+    /// the first of them ends the match, so only what comes before it counts.
+    fn can_fill(&self, incoming: &BookOrder) -> bool {
+        let mut levels: Box<dyn Iterator<Item = &PriceLevel>> = match incoming.side {
+            Side::BUY => Box::new(self.asks.range(..=incoming.price).map(|(_, l)| l)),
+            Side::SELL => Box::new(self.bids.range(incoming.price..).rev().map(|(_, l)| l)),
+        };
+        let Some(mode) = incoming.stp_mode.or(self.stp_mode) else {
+            // More than a `Decimal` holds is more than any order can ask for.
+            return levels
+                .try_fold(Decimal::ZERO, |sum, level| sum.checked_add(level.quantity))
+                .is_none_or(|available| available >= incoming.remaining);
+        };
+        let mut available = Decimal::ZERO;
+        for order in levels.flat_map(|level| self.level_orders(level)) {
+            if order.account == incoming.account {
+                match mode {
+                    StpMode::CANCEL_NEWEST | StpMode::CANCEL_BOTH => return false,
+                    StpMode::CANCEL_OLDEST | StpMode::DECREMENT_AND_CANCEL => continue,
+                }
+            }
+            available += order.remaining;
+            if available >= incoming.remaining {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Moves the book clock forward and drops GTD/DAY orders that are due.
+    pub fn advance_clock(&mut self, now: u64) {
+        self.now = now;
+        while let Some(due) = self.expiries.first_entry()
+            && *due.key() <= now
+        {
+            for id in due.remove() {
+                if let Some(order) = self.remove(&id) {
+                    self.close(order, OrderStatus::EXPIRED, Some(ReasonCode::TIME_EXPIRED));
+                }
+            }
+        }
+    }
+
+    /// Worst price a MARKET order may reach while sweeping the opposite side.
+    /// Without a guard the sweep is bounded only by the book itself. A
+    /// slippage so wide that the bound can't be represented is rejected.
+    fn market_price_limit(
+        &self,
+        side: &Side,
+        worst_price: Option<Decimal>,
+        max_slippage_bps: Option<Decimal>,
+    ) -> Result<Decimal, RejectReason> {
+        let slipped = |touch: Decimal, bps: Decimal| {
+            let factor = match side {
+                Side::BUY => Decimal::ONE.checked_add(bps / BPS_PER_UNIT),
+                Side::SELL => Decimal::ONE.checked_sub(bps / BPS_PER_UNIT),
+            };
+            factor
+                .and_then(|factor| touch.checked_mul(factor))
+                .ok_or(RejectReason::INVALID_SLIPPAGE)
+        };
+        Ok(match side {
+            Side::BUY => {
+                let mut limit = worst_price.unwrap_or(Decimal::MAX);
+                if let (Some(bps), Some(touch)) = (max_slippage_bps, self.best_ask()) {
+                    limit = limit.min(slipped(touch, bps)?);
+                }
+                limit
+            }
+            Side::SELL => {
+                let mut limit = worst_price.unwrap_or(Decimal::ZERO);
+                if let (Some(bps), Some(touch)) = (max_slippage_bps, self.best_bid()) {
+                    limit = limit.max(slipped(touch, bps)?);
+                }
+                limit
+            }
+        })
+    }
+
+    /// Walks the side a `side` order would take from, best level first as
+    /// `match_order` does, and reports what `size` would cost. Nothing is
+    /// traded and the book is left as it is. A size that isn't positive is
+    /// refused with INVALID_AMOUNT, and one whose totals can't be represented
+    /// with AMOUNT_OVERFLOW.
+    pub fn simulate_sweep(
+        &self,
+        side: Side,
+        size: SweepSize,
+    ) -> Result<SweepEstimate, RejectReason> {
+        let overflow = || RejectReason::AMOUNT_OVERFLOW;
+        let levels: Box<dyn Iterator<Item = (&Decimal, &PriceLevel)>> = match side {
+            Side::BUY => Box::new(self.asks.iter()),
+            Side::SELL => Box::new(self.bids.iter().rev()),
+        };
+        let mut left = match size {
+            SweepSize::Amount(amount) | SweepSize::Notional(amount) => amount,
+        };
+        if left <= Decimal::ZERO {
+            return Err(RejectReason::INVALID_AMOUNT);
+        }
+        let (mut filled, mut notional) = (Decimal::ZERO, Decimal::ZERO);
+        let mut worst_price = None;
+        let mut levels_consumed = 0;
+        for (&price, level) in levels {
+            if left <= Decimal::ZERO {
+                break;
+            }
+            let affordable = match size {
+                SweepSize::Amount(_) => left,
+                SweepSize::Notional(_) => left.checked_div(price).ok_or_else(overflow)?,
+            };
+            let qty = affordable.min(level.quantity);
+            let cost = match size {
+                // The level covers what is left, so all of it is spent; the
+                // rounded `qty * price` could leave a residue behind.
+                SweepSize::Notional(_) if affordable <= level.quantity => left,
+                _ => qty.checked_mul(price).ok_or_else(overflow)?,
+            };
+            left -= match size {
+                SweepSize::Amount(_) => qty,
+                SweepSize::Notional(_) => cost,
+            };
+            filled = filled.checked_add(qty).ok_or_else(overflow)?;
+            notional = notional.checked_add(cost).ok_or_else(overflow)?;
+            worst_price = Some(price);
+            levels_consumed += 1;
+        }
+        Ok(SweepEstimate {
+            side,
+            filled: filled.to_string(),
+            notional: notional.to_string(),
+            avg_price: (filled > Decimal::ZERO)
+                .then(|| (notional / filled).normalize().to_string()),
+            worst_price: worst_price.map(|p| p.to_string()),
+            levels_consumed,
+            unfilled: left.max(Decimal::ZERO).to_string(),
+        })
+    }
+
+    pub fn best_ask(&self) -> Option<Decimal> {
+        self.asks.first_key_value().map(|(price, _)| *price)
+    }
+
+    pub fn best_bid(&self) -> Option<Decimal> {
+        self.bids.last_key_value().map(|(price, _)| *price)
+    }
+
+    fn levels_mut(&mut self, side: &Side) -> &mut BTreeMap<Decimal, PriceLevel> {
+        match side {
+            Side::BUY => &mut self.bids,
+            Side::SELL => &mut self.asks,
+        }
+    }
+
+    /// Marks the level at `price` as changed for the next depth update.
+    fn touch_level(&mut self, side: &Side, price: Decimal) {
+        match side {
+            Side::BUY => self.dirty_bids.insert(price),
+            Side::SELL => self.dirty_asks.insert(price),
+        };
+    }
+
+    fn node(&self, handle: usize) -> &OrderNode {
+        self.orders[handle].as_ref().expect("Dangling order handle")
+    }
+
+    fn node_mut(&mut self, handle: usize) -> &mut OrderNode {
+        self.orders[handle].as_mut().expect("Dangling order handle")
+    }
+
+    /// Handle of the order at the front of the best level on `side`.
+    fn best_resting(&self, side: &Side) -> Option<usize> {
+        let level = match side {
+            Side::BUY => self.bids.last_key_value(),
+            Side::SELL => self.asks.first_key_value(),
+        };
+        level.and_then(|(_, level)| level.head)
+    }
+
+    fn level_orders<'a>(&'a self, level: &'a PriceLevel) -> impl Iterator<Item = &'a BookOrder> {
+        std::iter::successors(level.head, |&handle| self.node(handle).next)
+            .map(|handle| &self.node(handle).order)
+    }
+
+    fn add(&mut self, mut order: BookOrder) {
+        order.status = order.open_status();
+        let handle = match self.free_slots.pop() {
+            Some(handle) => handle,
+            None => {
+                self.orders.push(None);
+                self.orders.len() - 1
+            }
+        };
+        let level = self.levels_mut(&order.side).entry(order.price).or_default();
+        let prev = level.tail;
+        level.tail = Some(handle);
+        level.head.get_or_insert(handle);
+        level.quantity += order.remaining;
+        level.orders += 1;
+        if let Some(prev) = prev {
+            self.node_mut(prev).next = Some(handle);
+        }
+        self.touch_level(&order.side, order.price);
+        self.id_index.insert(order.id.clone(), handle);
+        if let Some(expires_at) = order.expires_at {
+            self.expiries
+                .entry(expires_at)
+                .or_default()
+                .push(order.id.clone());
+        }
+        self.orders[handle] = Some(OrderNode {
+            order,
+            prev,
+            next: None,
+        });
+    }
+
+    fn remove(&mut self, order_id: &str) -> Option<BookOrder> {
+        let handle = self.id_index.remove(order_id)?;
+        let node = self.orders[handle].take().expect("Dangling order handle");
+        self.free_slots.push(handle);
+        if let Some(prev) = node.prev {
+            self.node_mut(prev).next = node.next;
+        }
+        if let Some(next) = node.next {
+            self.node_mut(next).prev = node.prev;
+        }
+        let levels = self.levels_mut(&node.order.side);
+        let level = levels
+            .get_mut(&node.order.price)
+            .expect("Resting order without price level");
+        if level.head == Some(handle) {
+            level.head = node.next;
+        }
+        if level.tail == Some(handle) {
+            level.tail = node.prev;
+        }
+        level.quantity -= node.order.remaining;
+        level.orders -= 1;
+        if level.head.is_none() {
+            levels.remove(&node.order.price);
+        }
+        self.touch_level(&node.order.side, node.order.price);
+        if let Some(expires_at) = node.order.expires_at
+            && let Some(ids) = self.expiries.get_mut(&expires_at)
+        {
+            ids.retain(|id| *id != node.order.id);
+            if ids.is_empty() {
+                self.expiries.remove(&expires_at);
+            }
+        }
+        Some(node.order)
+    }
+
+    /// Takes `qty` off a resting order in place, keeping its queue position,
+    /// and keeps the level total in step.
+    fn reduce_resting(&mut self, handle: usize, qty: Decimal) {
+        let order = &mut self.node_mut(handle).order;
+        order.remaining -= qty;
+        let (side, price) = (order.side.clone(), order.price);
+        if let Some(level) = self.levels_mut(&side).get_mut(&price) {
+            level.quantity -= qty;
+        }
+        self.touch_level(&side, price);
+    }
+
+    /// Matches `incoming` against the opposite side. Returns true when
+    /// self-trade prevention requires the incoming order to be cancelled.
+    fn match_order(&mut self, incoming: &mut BookOrder) -> bool {
+        while incoming.remaining > Decimal::ZERO {
+            let Some(handle) = self.best_resting(&incoming.side.opposite()) else {
+                break;
+            };
+            let best_order = &self.node(handle).order;
+            let crosses = match incoming.side {
+                Side::BUY => incoming.price >= best_order.price,
+                Side::SELL => incoming.price <= best_order.price,
+            };
+            if !crosses {
+                break;
+            }
+            if incoming.account == best_order.account
+                && let Some(mode) = incoming.stp_mode.or(self.stp_mode)
+            {
+                if self.prevent_self_trade(incoming, handle, mode) {
+                    return true;
+                }
+                continue;
+            }
+            let trade_qty = incoming.remaining.min(best_order.remaining);
+            let trade_price = best_order.price;
+            let (buy_order_id, sell_order_id) = match incoming.side {
+                Side::BUY => (incoming.id.clone(), best_order.id.clone()),
+                Side::SELL => (best_order.id.clone(), incoming.id.clone()),
+            };
+            let trade = Trade {
+                trade_id: self.sequencer.next_trade_id(),
+                pair: self.pair.clone(),
+                buy_order_id,
+                sell_order_id,
+                price: trade_price.to_string(),
+                amount: trade_qty.to_string(),
+                ts: self.sequencer.next_seq(),
+                timestamp: self.timestamp,
+            };
+            self.trades.push(trade);
+            let last_fill = Some((trade_price, trade_qty));
+            incoming.record_fill(trade_qty, trade_price);
+            self.emit(incoming.report(fill_type(incoming), last_fill, None));
+            let resting = &mut self.node_mut(handle).order;
+            resting.record_fill(trade_qty, trade_price);
+            let (side, done) = (resting.side.clone(), resting.remaining <= Decimal::ZERO);
+            let resting_id = resting.id.clone();
+            let report = resting.report(fill_type(resting), last_fill, None);
+            self.emit(report);
+            if let Some(level) = self.levels_mut(&side).get_mut(&trade_price) {
+                level.quantity -= trade_qty;
+            }
+            self.touch_level(&side, trade_price);
+            if done && let Some(filled) = self.remove(&resting_id) {
+                self.close(filled, OrderStatus::FILLED, None);
+            }
+        }
+        false
+    }
+
+    /// Resolves a would-be self-trade against the resting order at `handle`
+    /// without printing a `Trade`. Returns true when the incoming order must
+    /// be cancelled.
+    fn prevent_self_trade(
+        &mut self,
+        incoming: &mut BookOrder,
+        handle: usize,
+        mode: StpMode,
+    ) -> bool {
+        match mode {
+            StpMode::CANCEL_NEWEST => true,
+            StpMode::CANCEL_OLDEST => {
+                self.cancel_self_trade(handle);
+                false
+            }
+            StpMode::CANCEL_BOTH => {
+                self.cancel_self_trade(handle);
+                true
+            }
+            StpMode::DECREMENT_AND_CANCEL => {
+                let qty = incoming.remaining.min(self.node(handle).order.remaining);
+                incoming.remaining -= qty;
+                incoming.quantity -= qty;
+                self.node_mut(handle).order.quantity -= qty;
+                self.reduce_resting(handle, qty);
+                let reason = Some(ReasonCode::SELF_TRADE_PREVENTED);
+                if self.node(handle).order.remaining <= Decimal::ZERO {
+                    self.cancel_self_trade(handle);
+                } else {
+                    let report = self
+                        .node(handle)
+                        .order
+                        .report(ExecType::RESTATED, None, reason);
+                    self.emit(report);
+                }
+                if incoming.remaining <= Decimal::ZERO {
+                    return true;
+                }
+                self.emit(incoming.report(ExecType::RESTATED, None, reason));
+                false
+            }
+        }
+    }
+
+    fn cancel_self_trade(&mut self, handle: usize) {
+        let order_id = self.node(handle).order.id.clone();
+        if let Some(order) = self.remove(&order_id) {
+            self.close(
+                order,
+                OrderStatus::CANCELLED,
+                Some(ReasonCode::SELF_TRADE_PREVENTED),
+            );
+        }
+    }
+
+    pub fn pair(&self) -> &str {
+        &self.pair
+    }
+
+    /// Trades and reports produced since the last drain.
+    pub fn drain(&mut self) -> (Vec<Trade>, Vec<ExecutionReport>) {
+        (
+            std::mem::take(&mut self.trades),
+            std::mem::take(&mut self.reports),
+        )
+    }
+
+    /// Snapshot of the resting orders, best price first on each side.
+    pub fn normalize(&self) -> Order {
+        let bids = self
+            .bids
+            .values()
+            .rev()
+            .flat_map(|level| self.level_orders(level))
+            .map(|order| Bid {
+                id: order.id.clone(),
+                price: order.price.to_string(),
+                remaining: order.remaining.to_string(),
+                account: order.account.clone(),
+                ts: order.ts,
+                timestamp: order.timestamp,
+            })
+            .collect();
+        let asks = self
+            .asks
+            .values()
+            .flat_map(|level| self.level_orders(level))
+            .map(|order| Ask {
+                id: order.id.clone(),
+                price: order.price.to_string(),
+                remaining: order.remaining.to_string(),
+                account: order.account.clone(),
+                ts: order.ts,
+                timestamp: order.timestamp,
+            })
+            .collect();
+
+        Order {
+            pair: self.pair.clone(),
+            bids,
+            asks,
+        }
+    }
+
+    /// Cancels every resting order, best price first, bids before asks.
+    fn cancel_all(&mut self, reason: ReasonCode) {
+        let ids: Vec<String> = self
+            .bids
+            .values()
+            .rev()
+            .chain(self.asks.values())
+            .flat_map(|level| self.level_orders(level))
+            .map(|order| order.id.clone())
+            .collect();
+        for id in ids {
+            if let Some(order) = self.remove(&id) {
+                self.close(order, OrderStatus::CANCELLED, Some(reason));
+            }
+        }
+    }
+
+    fn snapshot(&self) -> BookSnapshot {
+        let resting = self
+            .bids
+            .values()
+            .rev()
+            .chain(self.asks.values())
+            .flat_map(|level| self.level_orders(level))
+            .cloned()
+            .collect();
+        let closed = self
+            .closed_ids
+            .iter()
+            .map(|id| self.closed[id].clone())
+            .collect();
+        BookSnapshot {
+            pair: self.pair.clone(),
+            stp_mode: self.stp_mode,
+            now: self.now,
+            depth_seq: self.depth_seq,
+            resting,
+            closed,
+        }
+    }
+
+    /// Rebuilds a book by resting its orders again in priority order.
+    fn from_snapshot(snapshot: BookSnapshot) -> Self {
+        let mut book = OrderBook::new(snapshot.pair);
+        book.stp_mode = snapshot.stp_mode;
+        book.now = snapshot.now;
+        book.depth_seq = snapshot.depth_seq;
+        for order in snapshot.resting {
+            book.add(order);
+        }
+        book.dirty_bids.clear();
+        book.dirty_asks.clear();
+        for order in snapshot.closed {
+            book.closed_ids.push_back(order.id.clone());
+            book.closed.insert(order.id.clone(), order);
+        }
+        book
+    }
+
+    fn level(levels: &BTreeMap<Decimal, PriceLevel>, price: Decimal) -> Level {
+        let (quantity, orders) = levels
+            .get(&price)
+            .map_or((Decimal::ZERO, 0), |level| (level.quantity, level.orders));
+        Level {
+            price: price.to_string(),
+            quantity: quantity.to_string(),
+            orders,
+        }
+    }
+
+    /// `seq` of the last depth update taken from this book.
+    pub fn depth_seq(&self) -> u64 {
+        self.depth_seq
+    }
+
+    /// Every level of the book, as of the last depth update taken.
+    pub fn depth_snapshot(&self) -> DepthUpdate {
+        self.depth(usize::MAX, None)
+    }
+
+    /// Level-2 view of the best `levels` levels on each side. With a positive
+    /// `grouping`, prices are bucketed to multiples of it, bids down and asks
+    /// up, so a bucket never looks better than the orders in it.
+    pub fn depth(&self, levels: usize, grouping: Option<Decimal>) -> DepthUpdate {
+        let grouping = grouping.filter(|g| *g > Decimal::ZERO);
+        let bucket = |price: Decimal, round: fn(&Decimal) -> Decimal| match grouping {
+            Some(g) => (round(&(price / g)) * g).normalize(),
+            None => price,
+        };
+        DepthUpdate {
+            pair: self.pair.clone(),
+            seq: self.depth_seq,
+            bids: Self::group(self.bids.iter().rev(), levels, |p| {
+                bucket(p, Decimal::floor)
+            }),
+            asks: Self::group(self.asks.iter(), levels, |p| bucket(p, Decimal::ceil)),
+        }
+    }
+
+    /// Folds price levels, best first, into at most `levels` buckets.
+    fn group<'a>(
+        book_levels: impl Iterator<Item = (&'a Decimal, &'a PriceLevel)>,
+        levels: usize,
+        bucket: impl Fn(Decimal) -> Decimal,
+    ) -> Vec<Level> {
+        let mut grouped: Vec<(Decimal, Decimal, usize)> = Vec::new();
+        for (&price, level) in book_levels {
+            let price = bucket(price);
+            if let Some(last) = grouped.last_mut().filter(|last| last.0 == price) {
+                last.1 += level.quantity;
+                last.2 += level.orders;
+            } else if grouped.len() == levels {
+                break;
+            } else {
+                grouped.push((price, level.quantity, level.orders));
+            }
+        }
+        grouped
+            .into_iter()
+            .map(|(price, quantity, orders)| Level {
+                price: price.to_string(),
+                quantity: quantity.to_string(),
+                orders,
+            })
+            .collect()
+    }
+
+    /// The levels that changed since the last call, under the next `seq`;
+    /// `None` if the book didn't move.
+    pub fn take_depth_update(&mut self) -> Option<DepthUpdate> {
+        if self.dirty_bids.is_empty() && self.dirty_asks.is_empty() {
+            return None;
+        }
+        self.depth_seq += 1;
+        let bids = std::mem::take(&mut self.dirty_bids);
+        let asks = std::mem::take(&mut self.dirty_asks);
+        Some(DepthUpdate {
+            pair: self.pair.clone(),
+            seq: self.depth_seq,
+            bids: bids
+                .into_iter()
+                .rev()
+                .map(|p| Self::level(&self.bids, p))
+                .collect(),
+            asks: asks
+                .into_iter()
+                .map(|p| Self::level(&self.asks, p))
+                .collect(),
+        })
+    }
+}
+
+fn fill_type(order: &BookOrder) -> ExecType {
+    if order.remaining > Decimal::ZERO {
+        ExecType::PARTIAL_FILL
+    } else {
+        ExecType::FILL
+    }
+}
+
+/// Routes orders to one `OrderBook` per pair and merges their output into a
+/// single sequenced stream.
+pub struct MatcherEngine {
+    /// Keyed by pair so snapshots always come out in the same order.
+    books: BTreeMap<String, OrderBook>,
+    /// Lent to a book while it processes, so all books share one sequence.
+    sequencer: Sequencer,
+    clock: Box<dyn Clock>,
+    now: u64,
+    /// Self-trade prevention applied by every book; orders may override it.
+    stp_mode: Option<StpMode>,
+    /// Closed orders each book keeps; see `set_closed_retention`.
+    closed_retention: Option<usize>,
+    /// Listed pairs and their rules, handed to each book as it is created.
+    /// Without one, any pair trades without rules.
+    registry: Option<InstrumentRegistry>,
+    /// Trades and reports of all books, in the order they happened.
+    trades: Vec<Trade>,
+    reports: Vec<ExecutionReport>,
+    /// Collected only while `publish_depth` is on, for market-data feeds.
+    publish_depth: bool,
+    depth_updates: Vec<DepthUpdate>,
+    /// Where each order is written before it is applied, once opened.
+    journal: Option<Journal>,
+}
+
+impl MatcherEngine {
+    /// An engine on a `ReplayClock`.
+    pub fn new() -> Self {
+        Self::with_clock(Box::new(ReplayClock::default()))
+    }
+
+    pub fn with_clock(clock: Box<dyn Clock>) -> Self {
+        MatcherEngine {
+            books: BTreeMap::new(),
+            sequencer: Sequencer::new(),
+            clock,
+            now: 0,
+            stp_mode: None,
+            closed_retention: None,
+            registry: None,
+            trades: Vec::new(),
+            reports: Vec::new(),
+            publish_depth: false,
+            depth_updates: Vec::new(),
+            journal: None,
+        }
+    }
+
+    /// Decodes one JSON row and ingests it. A row that doesn't decode is
+    /// rejected as MALFORMED_ORDER under whatever ids it carries, so it only
+    /// rejects itself.
+    pub fn ingest_value(&mut self, value: serde_json::Value) -> Result<(), EngineError> {
+        let field = |name: &str| {
+            value
+                .get(name)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string()
+        };
+        let (order_id, pair) = (field("order_id"), field("pair"));
+        match serde_json::from_value::<RawOrder>(value) {
+            Ok(raw) => self.ingest(raw),
+            Err(err) => {
+                self.reject(&pair, &order_id, None, RejectReason::MALFORMED_ORDER);
+                Err(EngineError::Json(err))
+            }
+        }
+    }
+
+    /// Stamps `raw` from the clock, journals it if a journal is open, and
+    /// applies it. An order that can't be journaled is not applied.
+    ///
+    /// Orders are journaled before they are known to be accepted: whether
+    /// one is depends on the book it is applied to, and a rejected one still
+    /// moves the clock, expiring due orders, and gets its REJECT report.
+    /// Replaying it rejects it again at the same point.
+    pub fn ingest(&mut self, raw: RawOrder) -> Result<(), EngineError> {
+        let timestamp = self.clock.stamp(&raw);
+        if let Some(journal) = self.journal.as_mut() {
+            journal.append(self.sequencer.seq, timestamp, JournaledRef::Order(&raw))?;
+        }
+        self.apply(raw, timestamp)
+    }
+
+    /// Replays the journal at `path` into a fresh engine, rebuilding every
+    /// book, then journals each order ingested from here on. A torn record
+    /// left at the end by a crash is cut off. Trades and reports the replay
+    /// reproduces were delivered before the restart and are dropped.
+    /// Returns how many orders were replayed.
+    pub fn open_journal(&mut self, path: impl AsRef<Path>) -> Result<usize, EngineError> {
+        self.replay_journal(path.as_ref(), 0)
+    }
+
+    /// Writes every book, with its resting orders in priority order and the
+    /// states of its closed ones, to a new snapshot file in `dir`.
+    pub fn snapshot(&self, dir: impl AsRef<Path>) -> Result<PathBuf, EngineError> {
+        let snapshot = EngineSnapshot {
+            version: SNAPSHOT_VERSION,
+            seq: self.sequencer.seq,
+            trade_id: self.sequencer.trade_id,
+            now: self.now,
+            stp_mode: self.stp_mode,
+            statuses: self
+                .instruments()
+                .map(|instrument| (instrument.pair().to_string(), instrument.status))
+                .collect(),
+            books: self.books.values().map(OrderBook::snapshot).collect(),
+        };
+        snapshot.write(dir.as_ref())
+    }
+
+    /// Loads the latest snapshot in `dir` into a fresh engine, if there is
+    /// one, then replays only the journal entries taken after it and keeps
+    /// journaling. Returns how many entries were replayed.
+    pub fn restore(
+        &mut self,
+        dir: impl AsRef<Path>,
+        journal: impl AsRef<Path>,
+    ) -> Result<usize, EngineError> {
+        let mut from_seq = 0;
+        if let Some(snapshot) = EngineSnapshot::latest(dir.as_ref())? {
+            from_seq = snapshot.seq;
+            self.sequencer = Sequencer {
+                seq: snapshot.seq,
+                trade_id: snapshot.trade_id,
+            };
+            self.now = snapshot.now;
+            self.stp_mode = snapshot.stp_mode;
+            for (pair, status) in snapshot.statuses {
+                if let Some(instrument) = self.registry.as_mut().and_then(|r| r.get_mut(&pair)) {
+                    instrument.status = status;
+                }
+            }
+            self.books = snapshot
+                .books
+                .into_iter()
+                .map(|book| {
+                    let mut book = OrderBook::from_snapshot(book);
+                    book.rules = self.rules_for(&book.pair);
+                    book.closed_retention = self.closed_retention;
+                    book.forget_closed();
+                    (book.pair.clone(), book)
+                })
+                .collect();
+        }
+        self.replay_journal(journal.as_ref(), from_seq)
+    }
+
+    /// Opens the journal and applies its entries from sequence number
+    /// `from_seq` on; earlier ones are already in the books.
+    fn replay_journal(&mut self, path: &Path, from_seq: u64) -> Result<usize, EngineError> {
+        let (journal, entries) = Journal::open(path)?;
+        let entries: Vec<_> = entries.into_iter().filter(|e| e.seq >= from_seq).collect();
+        let replayed = entries.len();
+        for entry in entries {
+            // Rejections come out the same as the first time round.
+            let _ = self.replay_entry(entry);
+        }
+        self.drain();
+        self.drain_depth();
+        self.journal = Some(journal);
+        Ok(replayed)
+    }
+
+    /// Applies one journal entry at the sequence number and time it was
+    /// first applied at, without journaling it again.
+    pub fn replay_entry(&mut self, entry: JournalEntry) -> Result<(), EngineError> {
+        // Rows rejected before reaching `ingest` took sequence numbers without
+        // being journaled; resuming at the recorded one skips them exactly.
+        self.sequencer.seq = entry.seq;
+        match entry.input {
+            Journaled::Order(raw) => self.apply(raw, entry.timestamp),
+            Journaled::Admin { action, pair } => {
+                self.apply_admin(action, &pair);
+                Ok(())
+            }
+        }
+    }
+
+    /// Expires anything due by `timestamp` and routes `raw` to its pair's
+    /// book.
+    fn apply(&mut self, raw: RawOrder, timestamp: u64) -> Result<(), EngineError> {
+        if timestamp > self.now {
+            self.advance_clock(timestamp);
+        }
+        if let Err(reason) = self.route(&raw) {
+            return Err(self.reject(&raw.pair, &raw.order_id, raw.limit_price.clone(), reason));
+        }
+        let (now, stp_mode, retention) = (self.now, self.stp_mode, self.closed_retention);
+        let rules = self.rules_for(&raw.pair);
+        let book = self.books.entry(raw.pair.clone()).or_insert_with(|| {
+            let mut book = OrderBook::new(raw.pair.clone());
+            book.now = now;
+            book.stp_mode = stp_mode;
+            book.closed_retention = retention;
+            book.rules = rules;
+            book
+        });
+        book.sequencer = self.sequencer;
+        book.timestamp = timestamp;
+        let result = book.process(raw);
+        self.sequencer = book.sequencer;
+        self.trades.append(&mut book.trades);
+        self.reports.append(&mut book.reports);
+        let update = book.take_depth_update();
+        if self.publish_depth {
+            self.depth_updates.extend(update);
+        }
+        result
+    }
+
+    /// Engine-wide checks that no single book can make on its own.
+    fn route(&self, raw: &RawOrder) -> Result<(), RejectReason> {
+        if raw.pair.trim().is_empty() {
+            return Err(RejectReason::UNKNOWN_PAIR);
+        }
+        if let Some(registry) = &self.registry {
+            match registry.get(&raw.pair).map(Instrument::status) {
+                None => return Err(RejectReason::UNKNOWN_PAIR),
+                Some(TradingStatus::DELISTED) => return Err(RejectReason::PAIR_DELISTED),
+                Some(TradingStatus::HALTED) if !matches!(raw.type_op, Operation::DELETE) => {
+                    return Err(RejectReason::PAIR_HALTED);
+                }
+                Some(_) => {}
+            }
+        }
+        match raw.type_op {
+            Operation::CREATE if self.books.values().any(|b| b.knows(&raw.order_id)) => {
+                Err(RejectReason::DUPLICATE_ORDER_ID)
+            }
+            Operation::DELETE | Operation::MODIFY if !self.books.contains_key(&raw.pair) => {
+                Err(RejectReason::UNKNOWN_PAIR)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Records a rejection for a row that could not be routed to a book.
+    pub fn reject(
+        &mut self,
+        pair: &str,
+        order_id: &str,
+        price: Option<String>,
+        reason: RejectReason,
+    ) -> EngineError {
+        let mut report = ExecutionReport::rejected(pair, order_id, price, reason);
+        report.ts = self.sequencer.next_seq();
+        self.reports.push(report);
+        EngineError::Rejected {
+            order_id: order_id.to_string(),
+            reason,
+        }
+    }
+
+    /// Sets the engine clock (ns) and expires GTD/DAY orders in every book.
+    pub fn advance_clock(&mut self, now: u64) {
+        self.now = now;
+        for book in self.books.values_mut() {
+            book.sequencer = self.sequencer;
+            book.advance_clock(now);
+            self.sequencer = book.sequencer;
+            self.reports.append(&mut book.reports);
+            let update = book.take_depth_update();
+            if self.publish_depth {
+                self.depth_updates.extend(update);
+            }
+        }
+    }
+
+    /// The book of `pair`, once any order for it has been routed.
+    pub fn book(&self, pair: &str) -> Option<&OrderBook> {
+        self.books.get(pair)
+    }
+
+    /// Every level of `pair`; empty at `seq` 0 before its first order.
+    pub fn depth_snapshot(&self, pair: &str) -> DepthUpdate {
+        self.books.get(pair).map_or_else(
+            || DepthUpdate {
+                pair: pair.to_string(),
+                seq: 0,
+                bids: Vec::new(),
+                asks: Vec::new(),
+            },
+            OrderBook::depth_snapshot,
+        )
+    }
+
+    /// Cost of sweeping `pair` right now; `None` for a pair with no book.
+    /// See `OrderBook::simulate_sweep`.
+    pub fn simulate_sweep(
+        &self,
+        pair: &str,
+        side: Side,
+        size: SweepSize,
+    ) -> Option<Result<SweepEstimate, RejectReason>> {
+        self.books
+            .get(pair)
+            .map(|book| book.simulate_sweep(side, size))
+    }
+
+    /// Level-2 view of every book, by pair; see `OrderBook::depth`.
+    pub fn depth(&self, levels: usize, grouping: Option<Decimal>) -> Vec<DepthUpdate> {
+        self.books
+            .values()
+            .map(|b| b.depth(levels, grouping))
+            .collect()
+    }
+
+    /// Snapshot of every book, by pair, and all trades so far.
+    pub fn finish(&self) -> (Vec<Order>, Vec<Trade>) {
+        let orderbooks = self.books.values().map(|b| b.normalize()).collect();
+        (orderbooks, self.trades.clone())
+    }
+
+    /// Status of any order the engine has seen, whether resting or closed.
+    pub fn order_status(&self, order_id: &str) -> Option<OrderState> {
+        self.books
+            .values()
+            .find_map(|book| book.order_status(order_id))
+    }
+
+    pub fn reports(&self) -> &[ExecutionReport] {
+        &self.reports
+    }
+
+    /// Only accepts orders for the pairs in `registry` from now on, under
+    /// their rules, including pairs that already have a book.
+    pub fn set_registry(&mut self, registry: InstrumentRegistry) {
+        self.registry = Some(registry);
+        let pairs: Vec<String> = self.books.keys().cloned().collect();
+        for pair in pairs {
+            let rules = self.rules_for(&pair);
+            if let Some(book) = self.books.get_mut(&pair) {
+                book.rules = rules;
+            }
+        }
+    }
+
+    fn rules_for(&self, pair: &str) -> InstrumentRules {
+        self.instrument(pair)
+            .map(|instrument| instrument.rules().clone())
+            .unwrap_or_default()
+    }
+
+    /// Every listed pair; none without a registry.
+    pub fn instruments(&self) -> impl Iterator<Item = &Instrument> {
+        self.registry.iter().flat_map(InstrumentRegistry::iter)
+    }
+
+    pub fn instrument(&self, pair: &str) -> Option<&Instrument> {
+        self.registry.as_ref().and_then(|r| r.get(pair))
+    }
+
+    /// Stops `pair` taking new and amended orders; cancels still go through.
+    pub fn halt(&mut self, pair: &str) -> Result<(), EngineError> {
+        self.administer(AdminAction::HALT, pair)
+    }
+
+    /// Lets a halted `pair` trade again.
+    pub fn resume(&mut self, pair: &str) -> Result<(), EngineError> {
+        self.administer(AdminAction::RESUME, pair)
+    }
+
+    /// Cancels every resting order of `pair`, with a report each, and
+    /// rejects anything sent to it from then on.
+    pub fn delist(&mut self, pair: &str) -> Result<(), EngineError> {
+        self.administer(AdminAction::DELIST, pair)
+    }
+
+    /// Checks an admin action, journals it if a journal is open, and applies
+    /// it. Delisting is final, so a delisted pair takes no further action.
+    fn administer(&mut self, action: AdminAction, pair: &str) -> Result<(), EngineError> {
+        let status = self
+            .instrument(pair)
+            .ok_or_else(|| EngineError::UnknownInstrument {
+                pair: pair.to_string(),
+            })?
+            .status();
+        if status == TradingStatus::DELISTED {
+            return Err(EngineError::InvalidInstrument {
+                pair: pair.to_string(),
+                message: "pair is delisted".to_string(),
+            });
+        }
+        if let Some(journal) = self.journal.as_mut() {
+            let admin = JournaledRef::Admin { action, pair };
+            journal.append(self.sequencer.seq, self.now, admin)?;
+        }
+        self.apply_admin(action, pair);
+        Ok(())
+    }
+
+    fn apply_admin(&mut self, action: AdminAction, pair: &str) {
+        if let Some(instrument) = self.registry.as_mut().and_then(|r| r.get_mut(pair)) {
+            instrument.status = match action {
+                AdminAction::HALT => TradingStatus::HALTED,
+                AdminAction::RESUME => TradingStatus::TRADING,
+                AdminAction::DELIST => TradingStatus::DELISTED,
+            };
+        }
+        if action != AdminAction::DELIST {
+            return;
+        }
+        let Some(book) = self.books.get_mut(pair) else {
+            return;
+        };
+        book.sequencer = self.sequencer;
+        book.cancel_all(ReasonCode::INSTRUMENT_DELISTED);
+        self.sequencer = book.sequencer;
+        self.reports.append(&mut book.reports);
+        let update = book.take_depth_update();
+        if self.publish_depth {
+            self.depth_updates.extend(update);
+        }
+    }
+
+    /// Self-trade prevention for orders that don't carry their own mode.
+    pub fn set_stp_mode(&mut self, mode: Option<StpMode>) {
+        self.stp_mode = mode;
+    }
+
+    /// Keeps at most `retention` closed orders per book, forgetting the
+    /// oldest first; `None`, the default, keeps them all. Memory then stays
+    /// flat however many orders go through, but a forgotten order has no
+    /// status any more and its id is no longer caught as a duplicate.
+    pub fn set_closed_retention(&mut self, retention: Option<usize>) {
+        self.closed_retention = retention;
+        for book in self.books.values_mut() {
+            book.closed_retention = retention;
+            book.forget_closed();
+        }
+    }
+
+    /// Turns collection of level-2 depth updates on or off.
+    pub fn set_depth_updates(&mut self, enabled: bool) {
+        self.publish_depth = enabled;
+    }
+
+    /// Hands over the depth updates collected since the last call, one per
+    /// input and book that moved, in the order they happened.
+    pub fn drain_depth(&mut self) -> Vec<DepthUpdate> {
+        std::mem::take(&mut self.depth_updates)
+    }
+
+    /// Hands over the trades and reports produced so far, so a long-running
+    /// caller can flush them instead of letting them pile up.
+    pub fn drain(&mut self) -> (Vec<Trade>, Vec<ExecutionReport>) {
+        (
+            std::mem::take(&mut self.trades),
+            std::mem::take(&mut self.reports),
+        )
+    }
+}
+
+impl Default for MatcherEngine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Unit Tests
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_raw_order(
+        type_op: Operation,
+        account_id: &str,
+        amount: &str,
+        order_id: &str,
+        pair: &str,
+        limit_price: &str,
+        side: Side,
+    ) -> RawOrder {
+        RawOrder {
+            type_op,
+            account_id: account_id.to_string(),
+            amount: amount.to_string(),
+            order_id: order_id.to_string(),
+            pair: pair.to_string(),
+            limit_price: Some(limit_price.to_string()),
+            side,
+            order_type: OrderType::LIMIT,
+            worst_price: None,
+            max_slippage_bps: None,
+            time_in_force: TimeInForce::GTC,
+            expire_time: None,
+            post_only: false,
+            on_cross: CrossAction::REJECT,
+            stp_mode: None,
+            timestamp: None,
+        }
+    }
+
+    fn create_market_order(
+        order_id: &str,
+        amount: &str,
+        side: Side,
+        worst_price: Option<&str>,
+        max_slippage_bps: Option<&str>,
+    ) -> RawOrder {
+        RawOrder {
+            type_op: Operation::CREATE,
+            account_id: "taker".to_string(),
+            amount: amount.to_string(),
+            order_id: order_id.to_string(),
+            pair: "BTCUSD".to_string(),
+            limit_price: None,
+            side,
+            order_type: OrderType::MARKET,
+            worst_price: worst_price.map(str::to_string),
+            max_slippage_bps: max_slippage_bps.map(str::to_string),
+            time_in_force: TimeInForce::GTC,
+            expire_time: None,
+            post_only: false,
+            on_cross: CrossAction::REJECT,
+            stp_mode: None,
+            timestamp: None,
+        }
+    }
+
+    fn seed_asks(book: &mut OrderBook, levels: &[(&str, &str, &str)]) {
+        for (id, amount, price) in levels {
+            book.process(create_raw_order(
+                Operation::CREATE,
+                "maker",
+                amount,
+                id,
+                "BTCUSD",
+                price,
+                Side::SELL,
+            ))
+            .unwrap();
+        }
+    }
+
+    // ### Test 1: Adding Buy and Sell Orders
+    #[test]
+    fn test_add_orders() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+
+        let raw_buy = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "10",
+            "order1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        book.process(raw_buy).unwrap();
+
+        let raw_sell = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "10",
+            "order2",
+            "BTCUSD",
+            "101",
+            Side::SELL,
+        );
+        book.process(raw_sell).unwrap();
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.pair, "BTCUSD");
+        assert_eq!(normalized.bids.len(), 1, "Should have 1 bid");
+        assert_eq!(normalized.asks.len(), 1, "Should have 1 ask");
+        assert_eq!(normalized.bids[0].id, "order1");
+        assert_eq!(normalized.bids[0].price, "100");
+        assert_eq!(normalized.bids[0].remaining, "10");
+        assert_eq!(normalized.asks[0].id, "order2");
+        assert_eq!(normalized.asks[0].price, "101");
+        assert_eq!(normalized.asks[0].remaining, "10");
+    }
+
+    // ### Test 2: Deleting an Order
+    #[test]
+    fn test_delete_order() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+
+        let raw_buy = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "10",
+            "order1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        book.process(raw_buy).unwrap();
+
+        let raw_delete = create_raw_order(
+            Operation::DELETE,
+            "acc1",
+            "0",
+            "order1",
+            "BTCUSD",
+            "0",
+            Side::BUY,
+        );
+        book.process(raw_delete).unwrap();
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 0, "Bids should be empty");
+        assert_eq!(normalized.asks.len(), 0, "Asks should be empty");
+    }
+
+    // ### Test 3: Matching Orders (Full Match)
+    #[test]
+    fn test_match_orders() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+
+        let raw_sell = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "10",
+            "sell1",
+            "BTCUSD",
+            "100",
+            Side::SELL,
+        );
+        book.process(raw_sell).unwrap();
+
+        let raw_buy = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "5",
+            "buy1",
+            "BTCUSD",
+            "101",
+            Side::BUY,
+        );
+        book.process(raw_buy).unwrap();
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 0, "No bids should remain");
+        assert_eq!(normalized.asks.len(), 1, "One ask should remain");
+        assert_eq!(normalized.asks[0].id, "sell1");
+        assert_eq!(normalized.asks[0].remaining, "5");
+
+        assert_eq!(book.trades.len(), 1, "Should have 1 trade");
+        let trade = &book.trades[0];
+        assert_eq!(trade.pair, "BTCUSD");
+        assert_eq!(trade.buy_order_id, "buy1");
+        assert_eq!(trade.sell_order_id, "sell1");
+        assert_eq!(
+            trade.price, "100",
+            "Trade price should be the resting ask price"
+        );
+        assert_eq!(trade.amount, "5");
+    }
+
+    // ### Test 4: Partial Matching
+    #[test]
+    fn test_partial_match() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+
+        let raw_buy = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "10",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        book.process(raw_buy).unwrap();
+
+        let raw_sell = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "5",
+            "sell1",
+            "BTCUSD",
+            "100",
+            Side::SELL,
+        );
+        book.process(raw_sell).unwrap();
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 1, "One bid should remain");
+        assert_eq!(normalized.bids[0].id, "buy1");
+        assert_eq!(normalized.bids[0].remaining, "5");
+        assert_eq!(normalized.asks.len(), 0, "No asks should remain");
+
+        assert_eq!(book.trades.len(), 1, "Should have 1 trade");
+        let trade = &book.trades[0];
+        assert_eq!(trade.buy_order_id, "buy1");
+        assert_eq!(trade.sell_order_id, "sell1");
+        assert_eq!(
+            trade.price, "100",
+            "Trade price should be the resting bid price"
+        );
+        assert_eq!(trade.amount, "5");
+    }
+
+    // ### Test 5: Multiple Orders at Same Price (Time Priority)
+    #[test]
+    fn test_multiple_orders_same_price() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+
+        let raw_buy1 = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "5",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        book.process(raw_buy1).unwrap();
+
+        let raw_buy2 = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "5",
+            "buy2",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        book.process(raw_buy2).unwrap();
+
+        let raw_sell = create_raw_order(
+            Operation::CREATE,
+            "acc3",
+            "10",
+            "sell1",
+            "BTCUSD",
+            "100",
+            Side::SELL,
+        );
+        book.process(raw_sell).unwrap();
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 0, "No bids should remain");
+        assert_eq!(normalized.asks.len(), 0, "No asks should remain");
+
+        assert_eq!(book.trades.len(), 2, "Should have 2 trades");
+
+        assert_eq!(book.trades[0].buy_order_id, "buy1");
+        assert_eq!(book.trades[0].sell_order_id, "sell1");
+        assert_eq!(book.trades[0].amount, "5");
+        assert_eq!(book.trades[0].price, "100");
+
+        assert_eq!(book.trades[1].buy_order_id, "buy2");
+        assert_eq!(book.trades[1].sell_order_id, "sell1");
+        assert_eq!(book.trades[1].amount, "5");
+        assert_eq!(book.trades[1].price, "100");
+    }
+
+    // ### Test 6: Multiple Trading Pairs
+    #[test]
+    fn test_multiple_pairs() {
+        let mut engine = MatcherEngine::new();
+
+        let raw_btc = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "10",
+            "order1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        engine.ingest(raw_btc).unwrap();
+
+        let raw_eth = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "20",
+            "order2",
+            "ETHUSD",
+            "200",
+            Side::SELL,
+        );
+        engine.ingest(raw_eth).unwrap();
+
+        let (orderbooks, trades) = engine.finish();
+        assert_eq!(orderbooks.len(), 2, "Should have 2 order books");
+        assert_eq!(trades.len(), 0, "No trades should occur");
+
+        let btc_book = orderbooks.iter().find(|ob| ob.pair == "BTCUSD").unwrap();
+        assert_eq!(btc_book.bids.len(), 1, "BTCUSD should have 1 bid");
+        assert_eq!(btc_book.bids[0].id, "order1");
+        assert_eq!(btc_book.asks.len(), 0);
+
+        let eth_book = orderbooks.iter().find(|ob| ob.pair == "ETHUSD").unwrap();
+        assert_eq!(eth_book.bids.len(), 0);
+        assert_eq!(eth_book.asks.len(), 1, "ETHUSD should have 1 ask");
+        assert_eq!(eth_book.asks[0].id, "order2");
+    }
+
+    // ### Test 7: Market Order Sweeps Levels and Cancels the Remainder
+    #[test]
+    fn test_market_order_sweeps_book() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask1", "1", "100"), ("ask2", "2", "105")]);
+
+        book.process(create_market_order("mkt1", "5", Side::BUY, None, None))
+            .unwrap();
+
+        assert_eq!(book.trades.len(), 2, "Should sweep both ask levels");
+        assert_eq!(book.trades[0].sell_order_id, "ask1");
+        assert_eq!(book.trades[0].price, "100");
+        assert_eq!(book.trades[1].sell_order_id, "ask2");
+        assert_eq!(book.trades[1].price, "105");
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.asks.len(), 0, "Book side should be exhausted");
+        assert_eq!(normalized.bids.len(), 0, "Market remainder must not rest");
+    }
+
+    // ### Test 8: Market Order Slippage and Worst-Price Guards
+    #[test]
+    fn test_market_order_price_guards() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(
+            &mut book,
+            &[
+                ("ask1", "1", "100"),
+                ("ask2", "1", "100.5"),
+                ("ask3", "1", "102"),
+            ],
+        );
+
+        // 100 bps from a touch of 100 allows trading up to 101.
+        book.process(create_market_order(
+            "mkt1",
+            "3",
+            Side::BUY,
+            None,
+            Some("100"),
+        ))
+        .unwrap();
+        assert_eq!(book.trades.len(), 2, "Slippage guard should stop at 101");
+        assert_eq!(book.trades[1].sell_order_id, "ask2");
+
+        book.process(create_market_order(
+            "mkt2",
+            "1",
+            Side::BUY,
+            Some("101"),
+            None,
+        ))
+        .unwrap();
+        assert_eq!(book.trades.len(), 2, "Worst price should block the fill");
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.asks.len(), 1);
+        assert_eq!(normalized.asks[0].id, "ask3");
+        assert_eq!(normalized.bids.len(), 0, "Market remainders must not rest");
+
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask4", "1", "1000000")]);
+        let wide = create_market_order(
+            "mkt3",
+            "1",
+            Side::BUY,
+            None,
+            Some("70000000000000000000000000000"),
+        );
+        assert!(matches!(
+            book.process(wide),
+            Err(EngineError::Rejected {
+                reason: RejectReason::INVALID_SLIPPAGE,
+                ..
+            })
+        ));
+    }
+
+    // ### Test 9: IOC Cancels the Unfilled Remainder
+    #[test]
+    fn test_ioc_cancels_remainder() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask1", "1", "100")]);
+
+        let mut ioc = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "3",
+            "ioc1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        ioc.time_in_force = TimeInForce::IOC;
+        book.process(ioc).unwrap();
+
+        assert_eq!(book.trades.len(), 1);
+        assert_eq!(book.trades[0].amount, "1");
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 0, "IOC remainder must not rest");
+        assert_eq!(normalized.asks.len(), 0);
+    }
+
+    // ### Test 10: FOK Fills Completely or Not at All
+    #[test]
+    fn test_fok_all_or_nothing() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask1", "1", "100"), ("ask2", "1", "101")]);
+
+        let mut fok = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "2",
+            "fok1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        fok.time_in_force = TimeInForce::FOK;
+        book.process(fok).unwrap();
+        assert_eq!(book.trades.len(), 0, "Unfillable FOK must not trade");
+        assert_eq!(book.normalize().asks.len(), 2);
+
+        let mut fok = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "2",
+            "fok2",
+            "BTCUSD",
+            "101",
+            Side::BUY,
+        );
+        fok.time_in_force = TimeInForce::FOK;
+        book.process(fok).unwrap();
+        assert_eq!(book.trades.len(), 2, "Fillable FOK should fill fully");
+        assert_eq!(book.normalize().asks.len(), 0);
+
+        // Liquidity already taken or cancelled must not count towards a FOK.
+        seed_asks(&mut book, &[("ask3", "3", "100"), ("ask4", "2", "100")]);
+        book.process(create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "2",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        ))
+        .unwrap();
+        book.process(create_raw_order(
+            Operation::DELETE,
+            "maker",
+            "2",
+            "ask4",
+            "BTCUSD",
+            "100",
+            Side::SELL,
+        ))
+        .unwrap();
+        let mut fok = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "2",
+            "fok3",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        fok.time_in_force = TimeInForce::FOK;
+        book.process(fok).unwrap();
+        assert_eq!(book.trades.len(), 3, "Only 1 is left resting");
+        assert_eq!(book.normalize().asks[0].remaining, "1");
+    }
+
+    // ### Test 11: GTD and DAY Orders Expire Against the Engine Clock
+    #[test]
+    fn test_gtd_and_day_expiry() {
+        let mut engine = MatcherEngine::new();
+        engine.advance_clock(1_000);
+
+        let mut gtd = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "gtd1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        gtd.time_in_force = TimeInForce::GTD;
+        gtd.expire_time = Some(5_000);
+        engine.ingest(gtd).unwrap();
+
+        let mut day = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "day1",
+            "BTCUSD",
+            "99",
+            Side::BUY,
+        );
+        day.time_in_force = TimeInForce::DAY;
+        engine.ingest(day).unwrap();
+
+        engine.advance_clock(5_000);
+        let (orderbooks, _) = engine.finish();
+        assert_eq!(orderbooks[0].bids.len(), 1, "GTD order should be gone");
+        assert_eq!(orderbooks[0].bids[0].id, "day1");
+
+        engine.advance_clock(NANOS_PER_DAY);
+        let (orderbooks, _) = engine.finish();
+        assert_eq!(orderbooks[0].bids.len(), 0, "DAY order should be gone");
+        assert!(engine.books["BTCUSD"].expiries.is_empty());
+
+        // Within one batch, each order's timestamp moves the clock first.
+        let mut engine = MatcherEngine::new();
+        let mut gtd = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "gtd2",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        gtd.time_in_force = TimeInForce::GTD;
+        gtd.expire_time = Some(5_000);
+        gtd.timestamp = Some(1_000);
+        engine.ingest(gtd).unwrap();
+        let mut ask = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "1",
+            "ask1",
+            "BTCUSD",
+            "100",
+            Side::SELL,
+        );
+        ask.timestamp = Some(6_000);
+        engine.ingest(ask).unwrap();
+        let (orderbooks, trades) = engine.finish();
+        assert!(trades.is_empty(), "Expired order must not trade");
+        assert!(engine.books["BTCUSD"].expiries.is_empty());
+        assert_eq!(orderbooks[0].bids.len(), 0);
+        assert_eq!(orderbooks[0].asks.len(), 1);
+
+        // A DAY order on the clock's last day rests until the end of time.
+        let mut engine = MatcherEngine::new();
+        let mut day = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "day2",
+            "BTCUSD",
+            "99",
+            Side::BUY,
+        );
+        day.time_in_force = TimeInForce::DAY;
+        day.timestamp = Some(18_446_700_000_000_000_000);
+        engine.ingest(day).unwrap();
+        assert_eq!(
+            engine.order_status("day2").unwrap().status,
+            OrderStatus::NEW,
+            "Not expired on entry"
+        );
+        assert_eq!(
+            engine.books["BTCUSD"].expiries.keys().next(),
+            Some(&u64::MAX)
+        );
+    }
+
+    // ### Test 12: Post-Only Orders Reject or Slide Instead of Crossing
+    #[test]
+    fn test_post_only_reject_and_slide() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        book.set_rules(InstrumentRules {
+            tick_size: Some(Decimal::new(5, 1)),
+            ..InstrumentRules::default()
+        });
+        seed_asks(&mut book, &[("ask1", "1", "100")]);
+
+        let mut reject = create_raw_order(
+            Operation::CREATE,
+            "mm",
+            "1",
+            "po1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        reject.post_only = true;
+        book.process(reject).unwrap();
+
+        let mut slide = create_raw_order(
+            Operation::CREATE,
+            "mm",
+            "1",
+            "po2",
+            "BTCUSD",
+            "101",
+            Side::BUY,
+        );
+        slide.post_only = true;
+        slide.on_cross = CrossAction::SLIDE;
+        book.process(slide).unwrap();
+
+        let mut passive = create_raw_order(
+            Operation::CREATE,
+            "mm",
+            "1",
+            "po3",
+            "BTCUSD",
+            "98",
+            Side::BUY,
+        );
+        passive.post_only = true;
+        book.process(passive).unwrap();
+
+        assert_eq!(book.trades.len(), 0, "Post-only orders never take");
+        let reports: Vec<_> = book.reports.iter().skip(1).collect();
+        assert_eq!(reports.len(), 3, "One report per post-only order");
+        assert_eq!(reports[0].exec_type, ExecType::REJECT);
+        assert_eq!(reports[0].reason, Some(ReasonCode::POST_ONLY_WOULD_CROSS));
+        assert_eq!(reports[1].exec_type, ExecType::ACK);
+        assert_eq!(reports[1].reason, Some(ReasonCode::POST_ONLY_REPRICED));
+        assert_eq!(reports[1].price.as_deref(), Some("99.5"));
+        assert_eq!(reports[2].exec_type, ExecType::ACK);
+        assert_eq!(reports[2].reason, None);
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 2);
+        assert_eq!(normalized.bids[0].id, "po2");
+        assert_eq!(normalized.bids[0].price, "99.5");
+        assert_eq!(normalized.bids[1].id, "po3");
+        assert_eq!(normalized.asks.len(), 1);
+
+        // Without a tick size there is no "one tick behind" to slide to.
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask1", "1", "100")]);
+        let mut slide = create_raw_order(
+            Operation::CREATE,
+            "mm",
+            "1",
+            "po4",
+            "BTCUSD",
+            "101",
+            Side::BUY,
+        );
+        slide.post_only = true;
+        slide.on_cross = CrossAction::SLIDE;
+        assert!(matches!(
+            book.process(slide),
+            Err(EngineError::Rejected {
+                reason: RejectReason::SLIDE_WITHOUT_TICK,
+                ..
+            })
+        ));
+        assert_eq!(book.normalize().bids.len(), 0);
+    }
+
+    // ### Test 13: Reducing Quantity Keeps Time Priority
+    #[test]
+    fn test_modify_reduce_keeps_priority() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        for id in ["buy1", "buy2"] {
+            book.process(create_raw_order(
+                Operation::CREATE,
+                "acc1",
+                "5",
+                id,
+                "BTCUSD",
+                "100",
+                Side::BUY,
+            ))
+            .unwrap();
+        }
+
+        book.process(create_raw_order(
+            Operation::MODIFY,
+            "acc1",
+            "2",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        ))
+        .unwrap();
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids[0].id, "buy1", "Size-down keeps priority");
+        assert_eq!(normalized.bids[0].remaining, "2");
+
+        book.process(create_raw_order(
+            Operation::MODIFY,
+            "acc1",
+            "6",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        ))
+        .unwrap();
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 2);
+        assert_eq!(normalized.bids[0].id, "buy2", "Size-up loses priority");
+        assert_eq!(normalized.bids[1].id, "buy1");
+        assert_eq!(normalized.bids[1].remaining, "6");
+    }
+
+    // ### Test 14: Repricing Across the Spread Matches Again
+    #[test]
+    fn test_modify_price_rematches() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask1", "1", "101")]);
+        book.process(create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "3",
+            "buy1",
+            "BTCUSD",
+            "99",
+            Side::BUY,
+        ))
+        .unwrap();
+        assert_eq!(book.trades.len(), 0);
+
+        book.process(create_raw_order(
+            Operation::MODIFY,
+            "acc1",
+            "3",
+            "buy1",
+            "BTCUSD",
+            "101",
+            Side::BUY,
+        ))
+        .unwrap();
+        assert_eq!(book.trades.len(), 1, "Repriced order should cross");
+        assert_eq!(book.trades[0].buy_order_id, "buy1");
+        assert_eq!(book.trades[0].amount, "1");
+
+        let normalized = book.normalize();
+        assert_eq!(normalized.asks.len(), 0);
+        assert_eq!(normalized.bids.len(), 1);
+        assert_eq!(normalized.bids[0].price, "101");
+        assert_eq!(normalized.bids[0].remaining, "2");
+    }
+
+    // ### Test 15: Cancelling From the Middle of a Level Keeps FIFO Order
+    #[test]
+    fn test_cancel_inside_price_level() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(
+            &mut book,
+            &[
+                ("ask1", "1", "100"),
+                ("ask2", "1", "100"),
+                ("ask3", "1", "100"),
+            ],
+        );
+        book.process(create_raw_order(
+            Operation::DELETE,
+            "maker",
+            "0",
+            "ask2",
+            "BTCUSD",
+            "0",
+            Side::SELL,
+        ))
+        .unwrap();
+        assert_eq!(book.asks[&Decimal::from(100)].quantity, Decimal::from(2));
+
+        book.process(create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "2",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        ))
+        .unwrap();
+        assert_eq!(book.trades.len(), 2);
+        assert_eq!(book.trades[0].sell_order_id, "ask1");
+        assert_eq!(book.trades[1].sell_order_id, "ask3");
+        assert!(book.asks.is_empty(), "Empty level should be dropped");
+        assert!(book.id_index.is_empty(), "Filled orders leave the index");
+    }
+
+    /// The previous `BinaryHeap` book with lazy deletion, kept as the
+    /// baseline for `bench_price_levels_vs_heap`.
+    mod heap_book {
+        use super::*;
+        use std::cmp::{Ordering, Reverse};
+        use std::collections::BinaryHeap;
+
+        #[derive(Eq, PartialEq)]
+        struct BidBookOrder(BookOrder);
+
+        impl Ord for BidBookOrder {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0
+                    .price
+                    .cmp(&other.0.price)
+                    .then_with(|| other.0.ts.cmp(&self.0.ts))
+            }
+        }
+
+        impl PartialOrd for BidBookOrder {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        #[derive(Eq, PartialEq)]
+        struct AskBookOrder(BookOrder);
+
+        impl Ord for AskBookOrder {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0
+                    .price
+                    .cmp(&other.0.price)
+                    .then_with(|| self.0.ts.cmp(&other.0.ts))
+            }
+        }
+
+        impl PartialOrd for AskBookOrder {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        #[derive(Default)]
+        pub struct HeapBook {
+            bids: BinaryHeap<BidBookOrder>,
+            asks: BinaryHeap<Reverse<AskBookOrder>>,
+            id_index: HashMap<String, BookOrder>,
+        }
+
+        impl HeapBook {
+            pub fn add(&mut self, order: BookOrder) {
+                self.id_index.insert(order.id.clone(), order.clone());
+                match order.side {
+                    Side::BUY => self.bids.push(BidBookOrder(order)),
+                    Side::SELL => self.asks.push(Reverse(AskBookOrder(order))),
+                }
+            }
+
+            pub fn remove(&mut self, order_id: &str) {
+                self.id_index.remove(order_id);
+            }
+
+            pub fn best_bid(&mut self) -> Option<Decimal> {
+                let order = self.pop_active_top_bids()?;
+                let price = order.price;
+                self.bids.push(BidBookOrder(order));
+                Some(price)
+            }
+
+            pub fn match_sell(&mut self, incoming: &mut BookOrder) {
+                while incoming.remaining > Decimal::ZERO {
+                    let Some(mut best_order) = self.pop_active_top_bids() else {
+                        break;
+                    };
+                    if incoming.price > best_order.price {
+                        self.bids.push(BidBookOrder(best_order));
+                        break;
+                    }
+                    let trade_qty = incoming.remaining.min(best_order.remaining);
+                    incoming.remaining -= trade_qty;
+                    best_order.remaining -= trade_qty;
+                    self.id_index
+                        .insert(best_order.id.clone(), best_order.clone());
+                    if best_order.remaining > Decimal::ZERO {
+                        self.bids.push(BidBookOrder(best_order));
+                    }
+                }
+            }
+
+            fn pop_active_top_bids(&mut self) -> Option<BookOrder> {
+                while let Some(BidBookOrder(order)) = self.bids.pop() {
+                    if let Some(active_order) = self.id_index.get(&order.id)
+                        && active_order.remaining > Decimal::ZERO
+                    {
+                        return Some(active_order.clone());
+                    }
+                }
+                None
+            }
+
+            pub fn normalize_len(&self) -> usize {
+                let mut bids: Vec<_> = self
+                    .bids
+                    .iter()
+                    .filter_map(|BidBookOrder(order)| {
+                        self.id_index
+                            .get(&order.id)
+                            .filter(|o| o.remaining > Decimal::ZERO)
+                            .cloned()
+                    })
+                    .collect();
+                bids.sort_by(|a, b| b.price.cmp(&a.price).then_with(|| a.ts.cmp(&b.ts)));
+                let mut asks: Vec<_> = self
+                    .asks
+                    .iter()
+                    .filter_map(|Reverse(AskBookOrder(order))| {
+                        self.id_index
+                            .get(&order.id)
+                            .filter(|o| o.remaining > Decimal::ZERO)
+                            .cloned()
+                    })
+                    .collect();
+                asks.sort_by(|a, b| a.price.cmp(&b.price).then_with(|| a.ts.cmp(&b.ts)));
+                bids.len() + asks.len()
+            }
+        }
+    }
+
+    fn bench_order(id: usize, side: Side, price: u64, amount: u64) -> BookOrder {
+        BookOrder {
+            id: id.to_string(),
+            account: "bench".to_string(),
+            side,
+            pair: "BTCUSD".to_string(),
+            order_type: OrderType::LIMIT,
+            price: Decimal::from(price),
+            quantity: Decimal::from(amount),
+            remaining: Decimal::from(amount),
+            filled: Decimal::ZERO,
+            filled_notional: Decimal::ZERO,
+            status: OrderStatus::NEW,
+            ts: id as u64,
+            timestamp: 0,
+            time_in_force: TimeInForce::GTC,
+            expires_at: None,
+            stp_mode: None,
+            post_only: false,
+            on_cross: CrossAction::REJECT,
+        }
+    }
+
+    // ### Benchmark: Price-Level Book vs BinaryHeap With Lazy Deletion
+    // cargo test --release -- --ignored --nocapture bench_price_levels_vs_heap
+    #[test]
+    #[ignore = "benchmark, run explicitly in release mode"]
+    fn bench_price_levels_vs_heap() {
+        use std::time::{Duration, Instant};
+
+        const ORDERS: usize = 50_000;
+        const QUERIES: usize = 2_000;
+        const SNAPSHOTS: usize = 20;
+
+        // Rest ORDERS bids over 1000 prices, cancel 90% of them, then query
+        // the touch, take snapshots and sweep the remaining depth.
+        let run_levels = || {
+            let mut book = OrderBook::new("BTCUSD".to_string());
+            let mut phases = [Duration::ZERO; 4];
+            let start = Instant::now();
+            for i in 0..ORDERS {
+                book.add(bench_order(i, Side::BUY, 1_000 + (i % 1_000) as u64, 1));
+            }
+            for i in (0..ORDERS).filter(|i| i % 10 != 0) {
+                book.remove(&i.to_string());
+            }
+            phases[0] = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..QUERIES {
+                std::hint::black_box(book.best_bid());
+            }
+            phases[1] = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..SNAPSHOTS {
+                std::hint::black_box(book.normalize());
+            }
+            phases[2] = start.elapsed();
+            let start = Instant::now();
+            let mut sell = bench_order(ORDERS, Side::SELL, 0, ORDERS as u64);
+            book.match_order(&mut sell);
+            phases[3] = start.elapsed();
+            assert!(book.id_index.is_empty());
+            phases
+        };
+        let run_heap = || {
+            let mut book = heap_book::HeapBook::default();
+            let mut phases = [Duration::ZERO; 4];
+            let start = Instant::now();
+            for i in 0..ORDERS {
+                book.add(bench_order(i, Side::BUY, 1_000 + (i % 1_000) as u64, 1));
+            }
+            for i in (0..ORDERS).filter(|i| i % 10 != 0) {
+                book.remove(&i.to_string());
+            }
+            phases[0] = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..QUERIES {
+                std::hint::black_box(book.best_bid());
+            }
+            phases[1] = start.elapsed();
+            let start = Instant::now();
+            for _ in 0..SNAPSHOTS {
+                std::hint::black_box(book.normalize_len());
+            }
+            phases[2] = start.elapsed();
+            let start = Instant::now();
+            let mut sell = bench_order(ORDERS, Side::SELL, 0, ORDERS as u64);
+            book.match_sell(&mut sell);
+            phases[3] = start.elapsed();
+            phases
+        };
+
+        let levels = run_levels();
+        let heap = run_heap();
+        println!("{:<16}{:>14}{:>14}", "phase", "price levels", "binary heap");
+        for (i, phase) in ["add + cancel", "best price", "normalize", "sweep"]
+            .iter()
+            .enumerate()
+        {
+            println!("{:<16}{:>14?}{:>14?}", phase, levels[i], heap[i]);
+        }
+        let total = |phases: [Duration; 4]| phases.iter().sum::<Duration>();
+        assert!(
+            total(levels) < total(heap),
+            "Price-level book should beat the lazy-deletion heap"
+        );
+    }
+
+    // ### Test 16: Order Status Tracks Fills and Terminal States
+    #[test]
+    fn test_order_status_lifecycle() {
+        let mut engine = MatcherEngine::new();
+        for (id, amount, price) in [("ask1", "1", "100"), ("ask2", "2", "102")] {
+            engine
+                .ingest(create_raw_order(
+                    Operation::CREATE,
+                    "maker",
+                    amount,
+                    id,
+                    "BTCUSD",
+                    price,
+                    Side::SELL,
+                ))
+                .unwrap();
+        }
+        let status = engine.order_status("ask1").unwrap();
+        assert_eq!(status.status, OrderStatus::NEW);
+        assert_eq!(status.avg_price, None);
+
+        engine
+            .ingest(create_raw_order(
+                Operation::CREATE,
+                "taker",
+                "2",
+                "buy1",
+                "BTCUSD",
+                "102",
+                Side::BUY,
+            ))
+            .unwrap();
+        let buy = engine.order_status("buy1").unwrap();
+        assert_eq!(buy.status, OrderStatus::FILLED);
+        assert_eq!(buy.filled, "2");
+        assert_eq!(buy.remaining, "0");
+        assert_eq!(buy.avg_price.as_deref(), Some("101"));
+        assert_eq!(
+            engine.order_status("ask1").unwrap().status,
+            OrderStatus::FILLED
+        );
+        let ask2 = engine.order_status("ask2").unwrap();
+        assert_eq!(ask2.status, OrderStatus::PARTIALLY_FILLED);
+        assert_eq!(ask2.filled, "1");
+        assert_eq!(ask2.remaining, "1");
+
+        engine
+            .ingest(create_raw_order(
+                Operation::DELETE,
+                "maker",
+                "0",
+                "ask2",
+                "BTCUSD",
+                "0",
+                Side::SELL,
+            ))
+            .unwrap();
+        let ask2 = engine.order_status("ask2").unwrap();
+        assert_eq!(ask2.status, OrderStatus::CANCELLED);
+        assert_eq!(ask2.filled, "1", "Cancel keeps the cumulative fill");
+        assert!(engine.order_status("unknown").is_none());
+    }
+
+    // ### Test 17: Execution Reports Cover Every State Change
+    #[test]
+    fn test_execution_report_stream() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut book, &[("ask1", "1", "100"), ("ask2", "1", "101")]);
+
+        let mut ioc = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "3",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        ioc.time_in_force = TimeInForce::IOC;
+        book.process(ioc).unwrap();
+        book.process(create_raw_order(
+            Operation::DELETE,
+            "maker",
+            "0",
+            "ask2",
+            "BTCUSD",
+            "0",
+            Side::SELL,
+        ))
+        .unwrap();
+
+        let events: Vec<_> = book
+            .reports
+            .iter()
+            .map(|r| {
+                (
+                    r.order_id.as_str(),
+                    r.exec_type,
+                    r.leaves_qty.as_str(),
+                    r.cum_qty.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            events,
+            vec![
+                ("ask1", ExecType::ACK, "1", "0"),
+                ("ask2", ExecType::ACK, "1", "0"),
+                ("buy1", ExecType::ACK, "3", "0"),
+                ("buy1", ExecType::PARTIAL_FILL, "2", "1"),
+                ("ask1", ExecType::FILL, "0", "1"),
+                ("buy1", ExecType::CANCEL, "0", "1"),
+                ("ask2", ExecType::CANCEL, "0", "0"),
+            ]
+        );
+        assert_eq!(book.reports[3].last_price.as_deref(), Some("100"));
+        assert_eq!(book.reports[3].last_qty.as_deref(), Some("1"));
+        assert_eq!(book.reports[5].reason, Some(ReasonCode::UNFILLED_REMAINDER));
+        assert_eq!(book.reports[6].reason, Some(ReasonCode::USER_CANCEL));
+    }
+
+    // ### Test 18: Malformed Orders Are Rejected Without Stopping the Batch
+    #[test]
+    fn test_rejections_keep_batch_running() {
+        let mut engine = MatcherEngine::new();
+        let mut bad_price = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "bad1",
+            "BTCUSD",
+            "1O0",
+            Side::BUY,
+        );
+        let err = engine.ingest(bad_price).unwrap_err();
+        assert!(matches!(
+            err,
+            EngineError::Rejected {
+                reason: RejectReason::INVALID_PRICE,
+                ..
+            }
+        ));
+
+        bad_price = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "-1",
+            "bad2",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        assert!(engine.ingest(bad_price).is_err());
+
+        let good = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "good1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        engine.ingest(good).unwrap();
+
+        let cases = [
+            (
+                Operation::CREATE,
+                "huge",
+                "BTCUSD",
+                RejectReason::AMOUNT_OVERFLOW,
+            ),
+            (
+                Operation::CREATE,
+                "good1",
+                "BTCUSD",
+                RejectReason::DUPLICATE_ORDER_ID,
+            ),
+            (Operation::CREATE, "good2", "", RejectReason::UNKNOWN_PAIR),
+            (
+                Operation::DELETE,
+                "missing",
+                "BTCUSD",
+                RejectReason::UNKNOWN_ORDER,
+            ),
+            (
+                Operation::DELETE,
+                "good1",
+                "ETHUSD",
+                RejectReason::UNKNOWN_PAIR,
+            ),
+        ];
+        for (type_op, order_id, pair, expected) in cases {
+            let (amount, price) = match order_id {
+                "huge" => ("2", "70000000000000000000000000000"),
+                _ => ("1", "100"),
+            };
+            let raw = create_raw_order(type_op, "acc1", amount, order_id, pair, price, Side::BUY);
+            match engine.ingest(raw) {
+                Err(EngineError::Rejected { reason, .. }) => assert_eq!(reason, expected),
+                _ => panic!("{order_id} should be rejected with {expected}"),
+            }
+        }
+
+        let (orderbooks, _) = engine.finish();
+        assert_eq!(orderbooks.len(), 1);
+        assert_eq!(orderbooks[0].bids.len(), 1);
+        assert_eq!(orderbooks[0].bids[0].id, "good1");
+        let rejects = engine
+            .reports()
+            .iter()
+            .filter(|r| r.exec_type == ExecType::REJECT)
+            .count();
+        assert_eq!(rejects, 7, "Every bad row gets a reject report");
+
+        // A SELL fills at the bids, however low its own limit.
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        book.process(create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "bid1",
+            "BTCUSD",
+            "70000000000000000000000000000",
+            Side::BUY,
+        ))
+        .unwrap();
+        let sell = create_raw_order(
+            Operation::CREATE,
+            "acc2",
+            "2",
+            "sell1",
+            "BTCUSD",
+            "1",
+            Side::SELL,
+        );
+        assert!(matches!(
+            book.process(sell),
+            Err(EngineError::Rejected {
+                reason: RejectReason::AMOUNT_OVERFLOW,
+                ..
+            })
+        ));
+        assert!(book.trades.is_empty());
+        assert_eq!(book.normalize().bids[0].remaining, "1");
+    }
+
+    fn self_trade_book(mode: StpMode) -> OrderBook {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        book.stp_mode = Some(mode);
+        seed_asks(&mut book, &[("ask1", "2", "100"), ("ask2", "2", "100")]);
+        book.process(create_raw_order(
+            Operation::CREATE,
+            "maker",
+            "3",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        ))
+        .unwrap();
+        book
+    }
+
+    fn stp_cancels(book: &OrderBook) -> Vec<&str> {
+        book.reports
+            .iter()
+            .filter(|r| r.reason == Some(ReasonCode::SELF_TRADE_PREVENTED))
+            .filter(|r| r.exec_type == ExecType::CANCEL)
+            .map(|r| r.order_id.as_str())
+            .collect()
+    }
+
+    // ### Test 19: Self-Trade Prevention Modes
+    #[test]
+    fn test_self_trade_prevention_modes() {
+        let book = self_trade_book(StpMode::CANCEL_NEWEST);
+        assert!(book.trades.is_empty());
+        assert_eq!(stp_cancels(&book), vec!["buy1"]);
+        assert_eq!(book.normalize().asks.len(), 2);
+
+        let book = self_trade_book(StpMode::CANCEL_OLDEST);
+        assert!(book.trades.is_empty());
+        assert_eq!(stp_cancels(&book), vec!["ask1", "ask2"]);
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 1, "Newest order rests");
+        assert_eq!(normalized.bids[0].remaining, "3");
+
+        let book = self_trade_book(StpMode::CANCEL_BOTH);
+        assert_eq!(stp_cancels(&book), vec!["ask1", "buy1"]);
+        let normalized = book.normalize();
+        assert_eq!(normalized.asks.len(), 1);
+        assert_eq!(normalized.asks[0].id, "ask2");
+        assert!(normalized.bids.is_empty());
+
+        let book = self_trade_book(StpMode::DECREMENT_AND_CANCEL);
+        assert!(book.trades.is_empty());
+        assert_eq!(stp_cancels(&book), vec!["ask1", "buy1"]);
+        let normalized = book.normalize();
+        assert_eq!(normalized.asks.len(), 1);
+        assert_eq!(normalized.asks[0].remaining, "1");
+        let restated = book
+            .reports
+            .iter()
+            .filter(|r| r.exec_type == ExecType::RESTATED)
+            .count();
+        assert_eq!(restated, 2, "Partial decrements are restated");
+    }
+
+    // ### Test 20: Per-Order STP Mode Overrides the Engine Default
+    #[test]
+    fn test_self_trade_prevention_per_order() {
+        let mut engine = MatcherEngine::new();
+        engine.stp_mode = Some(StpMode::CANCEL_NEWEST);
+        engine
+            .ingest(create_raw_order(
+                Operation::CREATE,
+                "acc1",
+                "1",
+                "ask1",
+                "BTCUSD",
+                "100",
+                Side::SELL,
+            ))
+            .unwrap();
+        let mut buy = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "buy1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        buy.stp_mode = Some(StpMode::CANCEL_OLDEST);
+        engine.ingest(buy).unwrap();
+
+        assert_eq!(
+            engine.order_status("ask1").unwrap().status,
+            OrderStatus::CANCELLED
+        );
+        assert_eq!(
+            engine.order_status("buy1").unwrap().status,
+            OrderStatus::NEW
+        );
+        let (_, trades) = engine.finish();
+        assert!(trades.is_empty());
+    }
+
+    // ### Test 21: Engine Output Is Ordered by Pair and Execution
+    #[test]
+    fn test_engine_output_order() {
+        let mut engine = MatcherEngine::new();
+        let orders = [
+            ("s1", "ZEC/USDC", "100", Side::SELL),
+            ("s2", "ADA/USDC", "100", Side::SELL),
+            ("b1", "ZEC/USDC", "100", Side::BUY),
+            ("s3", "BTC/USDC", "100", Side::SELL),
+            ("b2", "ADA/USDC", "100", Side::BUY),
+            ("b3", "BTC/USDC", "100", Side::BUY),
+        ];
+        for (id, pair, price, side) in orders {
+            engine
+                .ingest(create_raw_order(
+                    Operation::CREATE,
+                    id,
+                    "1",
+                    id,
+                    pair,
+                    price,
+                    side,
+                ))
+                .unwrap();
+        }
+
+        let (orderbooks, trades) = engine.finish();
+        let pairs: Vec<_> = orderbooks.iter().map(|o| o.pair.as_str()).collect();
+        assert_eq!(pairs, vec!["ADA/USDC", "BTC/USDC", "ZEC/USDC"]);
+        let buys: Vec<_> = trades.iter().map(|t| t.buy_order_id.as_str()).collect();
+        assert_eq!(buys, vec!["b1", "b2", "b3"]);
+    }
+
+    // ### Test 22: One Gapless Sequence Across Books
+    #[test]
+    fn test_global_sequence_and_trade_ids() {
+        let mut engine = MatcherEngine::new();
+        let orders = [
+            ("s1", "BTC/USDC", Side::SELL),
+            ("s2", "ETH/USDC", Side::SELL),
+            ("b1", "BTC/USDC", Side::BUY),
+            ("b2", "ETH/USDC", Side::BUY),
+        ];
+        for (id, pair, side) in orders {
+            engine
+                .ingest(create_raw_order(
+                    Operation::CREATE,
+                    id,
+                    "1",
+                    id,
+                    pair,
+                    "100",
+                    side,
+                ))
+                .unwrap();
+        }
+
+        let (_, trades) = engine.finish();
+        let trade_ids: Vec<_> = trades.iter().map(|t| t.trade_id).collect();
+        assert_eq!(trade_ids, vec![1, 2]);
+        assert_ne!(trades[0].ts, trades[1].ts, "Trade seqs must not collide");
+
+        let mut seqs: Vec<_> = trades.iter().map(|t| t.ts).collect();
+        seqs.extend(engine.reports().iter().map(|r| r.ts));
+        seqs.extend(
+            engine
+                .books
+                .values()
+                .flat_map(|b| b.closed.values().map(|o| o.ts)),
+        );
+        seqs.sort_unstable();
+        let expected: Vec<u64> = (1..=seqs.len() as u64).collect();
+        assert_eq!(
+            seqs, expected,
+            "Inputs and events share one gapless sequence"
+        );
+    }
+
+    // ### Test 23: Replay Clock Stamps Wall-Clock Time Next To The Sequence
+    #[test]
+    fn test_replay_clock_timestamps() {
+        let mut engine = MatcherEngine::new();
+        let mut ask = create_raw_order(
+            Operation::CREATE,
+            "maker",
+            "2",
+            "a1",
+            "BTC/USDC",
+            "100",
+            Side::SELL,
+        );
+        ask.timestamp = Some(1_000);
+        engine.ingest(ask).unwrap();
+        let mut bid = create_raw_order(
+            Operation::CREATE,
+            "taker",
+            "1",
+            "b1",
+            "BTC/USDC",
+            "100",
+            Side::BUY,
+        );
+        bid.timestamp = Some(2_500);
+        engine.ingest(bid).unwrap();
+        // No timestamp recorded: the replay clock holds the last one.
+        let bid = create_raw_order(
+            Operation::CREATE,
+            "taker",
+            "1",
+            "b2",
+            "BTC/USDC",
+            "99",
+            Side::BUY,
+        );
+        engine.ingest(bid).unwrap();
+
+        let (orderbooks, trades) = engine.finish();
+        assert_eq!(trades.len(), 1);
+        assert_eq!(trades[0].timestamp, 2_500, "Trade takes the taker's time");
+        assert_eq!(
+            orderbooks[0].asks[0].timestamp, 1_000,
+            "Maker keeps its entry time"
+        );
+        assert_eq!(orderbooks[0].bids[0].timestamp, 2_500);
+        assert!(
+            orderbooks[0].bids[0].ts > trades[0].ts,
+            "Sequence still orders events"
+        );
+    }
+
+    // ### Test 24: CSV Headers Match The Serialized Fields
+    #[test]
+    fn test_csv_headers_match_fields() {
+        let mut book = OrderBook::new("BTC/USDC".to_string());
+        seed_asks(&mut book, &[("a1", "1", "100")]);
+        book.process(create_raw_order(
+            Operation::CREATE,
+            "taker",
+            "2",
+            "b1",
+            "BTC/USDC",
+            "100",
+            Side::BUY,
+        ))
+        .unwrap();
+
+        let keys = |value: serde_json::Value| -> Vec<String> {
+            value.as_object().unwrap().keys().cloned().collect()
+        };
+        let trade = serde_json::to_value(&book.trades[0]).unwrap();
+        let mut header: Vec<_> = TRADE_HEADER.iter().map(|h| h.to_string()).collect();
+        header.sort();
+        assert_eq!(keys(trade), header);
+
+        let order = book.normalize();
+        let rows: Vec<_> = order.rows().collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].side, Side::BUY);
+        let mut header: Vec<_> = BOOK_ROW_HEADER.iter().map(|h| h.to_string()).collect();
+        header.sort();
+        assert_eq!(keys(serde_json::to_value(&rows[0]).unwrap()), header);
+    }
+
+    // ### Test 25: Depth Snapshot Plus Updates Rebuild The Book
+    #[test]
+    fn test_depth_updates_rebuild_book() {
+        type Levels = BTreeMap<Decimal, (Decimal, usize)>;
+        fn apply(bids: &mut Levels, asks: &mut Levels, update: &DepthUpdate) {
+            for (levels, side) in [(bids, &update.bids), (asks, &update.asks)] {
+                for level in side {
+                    let price = Decimal::from_str(&level.price).unwrap();
+                    let quantity = Decimal::from_str(&level.quantity).unwrap();
+                    if quantity.is_zero() {
+                        levels.remove(&price);
+                    } else {
+                        levels.insert(price, (quantity, level.orders));
+                    }
+                }
+            }
+        }
+        fn aggregate<'a>(orders: impl Iterator<Item = (&'a str, &'a str)>) -> Levels {
+            let mut levels = Levels::new();
+            for (price, remaining) in orders {
+                let level = levels.entry(Decimal::from_str(price).unwrap()).or_default();
+                level.0 += Decimal::from_str(remaining).unwrap();
+                level.1 += 1;
+            }
+            levels
+        }
+
+        let mut engine = MatcherEngine::new();
+        engine.set_depth_updates(true);
+        let order = |op, id: &str, amount: &str, price: &str, side| {
+            create_raw_order(op, id, amount, id, "BTC/USDC", price, side)
+        };
+        let orders = [
+            order(Operation::CREATE, "a1", "2", "101", Side::SELL),
+            order(Operation::CREATE, "a2", "1", "101", Side::SELL),
+            order(Operation::CREATE, "a3", "1", "102", Side::SELL),
+            order(Operation::CREATE, "b1", "3", "99", Side::BUY),
+        ];
+        for raw in orders {
+            engine.ingest(raw).unwrap();
+        }
+        let snapshot = engine.depth_snapshot("BTC/USDC");
+        let (mut bids, mut asks) = (Levels::new(), Levels::new());
+        apply(&mut bids, &mut asks, &snapshot);
+        // Updates taken before the snapshot are already part of it.
+        let stale = engine.drain_depth();
+        assert_eq!(stale.last().unwrap().seq, snapshot.seq);
+
+        let orders = [
+            order(Operation::CREATE, "b2", "2.5", "101", Side::BUY),
+            order(Operation::MODIFY, "b1", "1", "99", Side::BUY),
+            order(Operation::DELETE, "a3", "0", "0", Side::SELL),
+            order(Operation::CREATE, "b3", "1", "98", Side::BUY),
+            order(Operation::CREATE, "a4", "4", "98", Side::SELL),
+        ];
+        for raw in orders {
+            engine.ingest(raw).unwrap();
+        }
+        let updates = engine.drain_depth();
+        let seqs: Vec<_> = updates.iter().map(|u| u.seq).collect();
+        let expected: Vec<_> = (snapshot.seq + 1..=snapshot.seq + 5).collect();
+        assert_eq!(seqs, expected, "One gapless update per input");
+        for update in &updates {
+            apply(&mut bids, &mut asks, update);
+        }
+
+        let book = engine.book("BTC/USDC").unwrap().normalize();
+        assert_eq!(
+            bids,
+            aggregate(
+                book.bids
+                    .iter()
+                    .map(|b| (b.price.as_str(), b.remaining.as_str()))
+            )
+        );
+        assert_eq!(
+            asks,
+            aggregate(
+                book.asks
+                    .iter()
+                    .map(|a| (a.price.as_str(), a.remaining.as_str()))
+            )
+        );
+        assert!(asks.contains_key(&Decimal::from(98)));
+    }
+
+    // ### Test 26: Depth Aggregates, Truncates And Groups Levels
+    #[test]
+    fn test_depth_levels_and_grouping() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(
+            &mut book,
+            &[
+                ("a1", "1", "101.5"),
+                ("a2", "2", "101.5"),
+                ("a3", "1", "104"),
+                ("a4", "1", "111"),
+            ],
+        );
+        for (id, price) in [("b1", "99.99"), ("b2", "95"), ("b3", "89")] {
+            book.process(create_raw_order(
+                Operation::CREATE,
+                "bidder",
+                "1",
+                id,
+                "BTCUSD",
+                price,
+                Side::BUY,
+            ))
+            .unwrap();
+        }
+        let prices = |levels: &[Level]| -> Vec<(String, String, usize)> {
+            levels
+                .iter()
+                .map(|l| (l.price.clone(), l.quantity.clone(), l.orders))
+                .collect()
+        };
+        let own = |p: &str, q: &str, n| (p.to_string(), q.to_string(), n);
+
+        let depth = book.depth(2, None);
+        assert_eq!(
+            prices(&depth.asks),
+            vec![own("101.5", "3", 2), own("104", "1", 1)]
+        );
+        assert_eq!(
+            prices(&depth.bids),
+            vec![own("99.99", "1", 1), own("95", "1", 1)]
+        );
+
+        let grouped = book.depth(10, Some(Decimal::from(10)));
+        assert_eq!(
+            prices(&grouped.asks),
+            vec![own("110", "4", 3), own("120", "1", 1)],
+            "Asks round up"
+        );
+        assert_eq!(
+            prices(&grouped.bids),
+            vec![own("90", "2", 2), own("80", "1", 1)],
+            "Bids round down"
+        );
+        assert_eq!(
+            prices(&book.depth(usize::MAX, None).asks),
+            prices(&book.depth_snapshot().asks)
+        );
+    }
+
+    // ### Test 27: Simulated Sweeps Leave The Book Untouched
+    #[test]
+    fn test_simulate_sweep() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        seed_asks(
+            &mut book,
+            &[("a1", "1", "100"), ("a2", "1", "100"), ("a3", "2", "105")],
+        );
+        let before = serde_json::to_string(&book.normalize()).unwrap();
+
+        let sweep = book
+            .simulate_sweep(Side::BUY, SweepSize::Amount(Decimal::from(3)))
+            .unwrap();
+        assert_eq!(sweep.filled, "3");
+        assert_eq!(sweep.notional, "305");
+        assert_eq!(
+            sweep.avg_price.as_deref(),
+            Some("101.66666666666666666666666667")
+        );
+        assert_eq!(sweep.worst_price.as_deref(), Some("105"));
+        assert_eq!(sweep.levels_consumed, 2);
+        assert_eq!(sweep.unfilled, "0");
+
+        let sweep = book
+            .simulate_sweep(Side::BUY, SweepSize::Notional(Decimal::from(410)))
+            .unwrap();
+        assert_eq!(sweep.filled, "4");
+        assert_eq!(sweep.notional, "410");
+        assert_eq!(sweep.unfilled, "0");
+
+        let sweep = book
+            .simulate_sweep(Side::BUY, SweepSize::Amount(Decimal::from(5)))
+            .unwrap();
+        assert_eq!(sweep.filled, "4");
+        assert_eq!(sweep.unfilled, "1", "More than the book holds");
+
+        let sweep = book
+            .simulate_sweep(Side::SELL, SweepSize::Amount(Decimal::ONE))
+            .unwrap();
+        assert_eq!(sweep.filled, "0");
+        assert_eq!(sweep.avg_price, None, "No bids to sell into");
+        assert_eq!(sweep.levels_consumed, 0);
+
+        let mut cheap = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut cheap, &[("c1", "1", "0.0000000001")]);
+        assert!(matches!(
+            cheap.simulate_sweep(Side::BUY, SweepSize::Notional(Decimal::MAX)),
+            Err(RejectReason::AMOUNT_OVERFLOW)
+        ));
+        for size in [Decimal::ZERO, Decimal::NEGATIVE_ONE] {
+            assert!(matches!(
+                book.simulate_sweep(Side::BUY, SweepSize::Amount(size)),
+                Err(RejectReason::INVALID_AMOUNT)
+            ));
+        }
+
+        // 100 / 3 doesn't divide exactly; the rounding must not spill over.
+        let mut thirds = OrderBook::new("BTCUSD".to_string());
+        seed_asks(&mut thirds, &[("t1", "50", "3"), ("t2", "1", "4")]);
+        let sweep = thirds
+            .simulate_sweep(Side::BUY, SweepSize::Notional(Decimal::from(100)))
+            .unwrap();
+        assert_eq!(sweep.levels_consumed, 1);
+        assert_eq!(sweep.worst_price.as_deref(), Some("3"));
+        assert_eq!(sweep.notional, "100");
+        assert_eq!(sweep.unfilled, "0");
+
+        assert_eq!(serde_json::to_string(&book.normalize()).unwrap(), before);
+        assert_eq!(book.id_index.len(), 3);
+        assert!(book.trades.is_empty());
+    }
+
+    // ### Test 28: Journal Replay Rebuilds The Engine And Drops Torn Tails
+    #[test]
+    fn test_journal_replay_and_torn_tail() {
+        let path = std::env::temp_dir().join(format!("journal-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let state = |engine: &MatcherEngine| {
+            let (books, _) = engine.finish();
+            let status = engine.order_status("s1").map(|s| s.status);
+            let sequencer = (engine.sequencer.seq, engine.sequencer.trade_id);
+            (serde_json::to_string(&books).unwrap(), status, sequencer)
+        };
+
+        let mut engine = MatcherEngine::with_clock(Box::new(SystemClock));
+        assert_eq!(engine.open_journal(&path).unwrap(), 0);
+        let orders = [
+            ("s1", "BTC/USDC", "2", "100", Side::SELL),
+            ("b1", "BTC/USDC", "0.5", "101", Side::BUY),
+            ("s2", "ETH/USDC", "1", "10", Side::SELL),
+            ("b1", "BTC/USDC", "1", "99", Side::BUY),
+            ("b2", "BTC/USDC", "1", "99", Side::BUY),
+        ];
+        for (id, pair, amount, price, side) in orders {
+            let _ = engine.ingest(create_raw_order(
+                Operation::CREATE,
+                id,
+                amount,
+                id,
+                pair,
+                price,
+                side,
+            ));
+        }
+        // Rejected before it reaches the journal, but still takes a seq.
+        let _ = engine.ingest_value(serde_json::json!({"order_id": "junk"}));
+        engine
+            .ingest(create_raw_order(
+                Operation::DELETE,
+                "b2",
+                "0",
+                "b2",
+                "BTC/USDC",
+                "0",
+                Side::BUY,
+            ))
+            .unwrap();
+        let expected = state(&engine);
+        drop(engine);
+
+        let mut restored = MatcherEngine::with_clock(Box::new(SystemClock));
+        assert_eq!(restored.open_journal(&path).unwrap(), 6);
+        assert_eq!(state(&restored), expected);
+        assert!(restored.drain().0.is_empty(), "Replayed trades are dropped");
+        drop(restored);
+
+        let intact = std::fs::metadata(&path).unwrap().len();
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap();
+        io::Write::write_all(&mut file, b"0badf00d {\"seq\":4").unwrap();
+        let mut restored = MatcherEngine::with_clock(Box::new(SystemClock));
+        assert_eq!(restored.open_journal(&path).unwrap(), 6);
+        assert_eq!(state(&restored), expected);
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), intact);
+        drop(restored);
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes[12] ^= 1;
+        std::fs::write(&path, bytes).unwrap();
+        let err = MatcherEngine::new().open_journal(&path).unwrap_err();
+        assert!(matches!(err, EngineError::CorruptJournal { offset: 0 }));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // ### Test 29: Snapshot Plus Journal Restores An Uninterrupted Run
+    #[test]
+    fn test_snapshot_restore_matches_uninterrupted_run() {
+        let dir = std::env::temp_dir().join(format!("snapshots-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let journal = dir.join("journal.log");
+        let orders = || {
+            let mut gtd = create_raw_order(
+                Operation::CREATE,
+                "gtd",
+                "1",
+                "g1",
+                "BTC/USDC",
+                "90",
+                Side::BUY,
+            );
+            gtd.time_in_force = TimeInForce::GTD;
+            gtd.expire_time = Some(5_000);
+            let rows = [
+                (Operation::CREATE, "s1", "2", "100", Side::SELL),
+                (Operation::CREATE, "s2", "1", "100", Side::SELL),
+                (Operation::CREATE, "b1", "0.5", "101", Side::BUY),
+                (Operation::MODIFY, "s2", "0.5", "100", Side::SELL),
+                (Operation::CREATE, "b2", "1", "95", Side::BUY),
+                (Operation::CREATE, "b3", "3", "100", Side::BUY),
+                (Operation::DELETE, "b2", "0", "0", Side::BUY),
+            ];
+            let mut raws = vec![gtd];
+            raws.extend(rows.into_iter().map(|(op, id, amount, price, side)| {
+                create_raw_order(op, id, amount, id, "BTC/USDC", price, side)
+            }));
+            for (i, raw) in raws.iter_mut().enumerate() {
+                raw.timestamp = Some(1_000 * (i as u64 + 1));
+            }
+            raws
+        };
+        let state = |engine: &MatcherEngine| {
+            let (books, _) = engine.finish();
+            let statuses: Vec<_> = ["g1", "s1", "s2", "b1", "b2", "b3"]
+                .iter()
+                .map(|id| engine.order_status(id).map(|s| s.status))
+                .collect();
+            let sequencer = (engine.sequencer.seq, engine.sequencer.trade_id);
+            (serde_json::to_string(&books).unwrap(), statuses, sequencer)
+        };
+
+        let mut uninterrupted = MatcherEngine::new();
+        for raw in orders() {
+            let _ = uninterrupted.ingest(raw);
+        }
+
+        let mut engine = MatcherEngine::new();
+        engine.open_journal(&journal).unwrap();
+        for (i, raw) in orders().into_iter().enumerate() {
+            if i == 4 {
+                engine.snapshot(&dir).unwrap();
+            }
+            let _ = engine.ingest(raw);
+        }
+        drop(engine);
+
+        let mut restored = MatcherEngine::new();
+        assert_eq!(restored.restore(&dir, &journal).unwrap(), 4);
+        assert_eq!(state(&restored), state(&uninterrupted));
+        assert_eq!(
+            restored.order_status("g1").unwrap().status,
+            OrderStatus::EXPIRED
+        );
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // ### Test 30: Instrument Rules Reject Off-Grid And Out-Of-Range Orders
+    #[test]
+    fn test_instrument_rules() {
+        let rules = InstrumentRules {
+            tick_size: Some(Decimal::new(5, 1)),
+            lot_size: Some(Decimal::new(1, 2)),
+            min_amount: Some(Decimal::new(5, 2)),
+            max_amount: Some(Decimal::from(10)),
+            min_notional: Some(Decimal::from(10)),
+        };
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        book.set_rules(rules);
+
+        let cases = [
+            ("100.25", "1", RejectReason::PRICE_NOT_ON_TICK),
+            ("100.5", "0.005", RejectReason::AMOUNT_NOT_ON_LOT),
+            ("100.5", "0.01", RejectReason::AMOUNT_BELOW_MIN),
+            ("100.5", "10.01", RejectReason::AMOUNT_ABOVE_MAX),
+            ("100", "0.09", RejectReason::NOTIONAL_BELOW_MIN),
+            (
+                "70000000000000000000000000000",
+                "10",
+                RejectReason::AMOUNT_OVERFLOW,
+            ),
+        ];
+        for (i, (price, amount, reason)) in cases.into_iter().enumerate() {
+            let id = format!("bad{i}");
+            let raw = create_raw_order(
+                Operation::CREATE,
+                "acc1",
+                amount,
+                &id,
+                "BTCUSD",
+                price,
+                Side::SELL,
+            );
+            let err = book.process(raw).unwrap_err();
+            assert!(
+                matches!(err, EngineError::Rejected { reason: r, .. } if r == reason),
+                "{price} x {amount}: {err}"
+            );
+        }
+        assert!(book.asks.is_empty());
+
+        let good = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "0.1",
+            "s1",
+            "BTCUSD",
+            "100.5",
+            Side::SELL,
+        );
+        book.process(good).unwrap();
+        let off_tick = create_raw_order(
+            Operation::MODIFY,
+            "acc1",
+            "0.1",
+            "s1",
+            "BTCUSD",
+            "100.7",
+            Side::SELL,
+        );
+        assert!(book.process(off_tick).is_err());
+        let mut market = create_market_order("m1", "0.05", Side::BUY, None, None);
+        market.account_id = "acc2".to_string();
+        book.process(market).unwrap();
+        assert_eq!(
+            book.trades.len(),
+            1,
+            "MARKET orders skip the notional check"
+        );
+
+        let unruled = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "0.000000001",
+            "e1",
+            "ETHUSD",
+            "63500.123456789",
+            Side::SELL,
+        );
+        OrderBook::new("ETHUSD".to_string())
+            .process(unruled)
+            .unwrap();
+    }
+
+    // ### Test 31: Registry Rejects Unknown Pairs And Halts, Resumes And Delists
+    #[test]
+    fn test_instrument_registry() {
+        assert!(Instrument::new("BTCUSD", InstrumentRules::default()).is_err());
+        assert!(Instrument::new("BTC/", InstrumentRules::default()).is_err());
+        let mut registry = InstrumentRegistry::default();
+        let btc = Instrument::new("BTC/USDC", InstrumentRules::default()).unwrap();
+        assert_eq!((btc.base(), btc.quote()), ("BTC", "USDC"));
+        registry.list(btc);
+
+        let path = std::env::temp_dir().join(format!("registry-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut engine = MatcherEngine::new();
+        engine.set_registry(registry.clone());
+        engine.open_journal(&path).unwrap();
+        let order = |op: Operation, id: &str, price: &str, side: Side| {
+            create_raw_order(op, id, "1", id, "BTC/USDC", price, side)
+        };
+        let rejection = |result: Result<(), EngineError>| match result {
+            Err(EngineError::Rejected { reason, .. }) => Some(reason),
+            _ => None,
+        };
+
+        let typo = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "t1",
+            "BTC/USCD",
+            "100",
+            Side::BUY,
+        );
+        assert_eq!(
+            rejection(engine.ingest(typo)),
+            Some(RejectReason::UNKNOWN_PAIR)
+        );
+        assert!(engine.book("BTC/USCD").is_none(), "No phantom book");
+
+        engine
+            .ingest(order(Operation::CREATE, "b1", "99", Side::BUY))
+            .unwrap();
+        engine
+            .ingest(order(Operation::CREATE, "b2", "98", Side::BUY))
+            .unwrap();
+        engine
+            .ingest(order(Operation::CREATE, "s1", "101", Side::SELL))
+            .unwrap();
+        engine.halt("BTC/USDC").unwrap();
+        assert_eq!(
+            rejection(engine.ingest(order(Operation::CREATE, "s2", "102", Side::SELL))),
+            Some(RejectReason::PAIR_HALTED)
+        );
+        engine
+            .ingest(order(Operation::DELETE, "b2", "98", Side::BUY))
+            .unwrap();
+        engine.resume("BTC/USDC").unwrap();
+        engine
+            .ingest(order(Operation::CREATE, "s3", "102", Side::SELL))
+            .unwrap();
+
+        engine.drain();
+        engine.delist("BTC/USDC").unwrap();
+        let (_, reports) = engine.drain();
+        let cancelled: Vec<_> = reports
+            .iter()
+            .map(|r| (r.order_id(), r.exec_type(), r.reason()))
+            .collect();
+        let delisted = Some(ReasonCode::INSTRUMENT_DELISTED);
+        assert_eq!(
+            cancelled,
+            [
+                ("b1", ExecType::CANCEL, delisted),
+                ("s1", ExecType::CANCEL, delisted),
+                ("s3", ExecType::CANCEL, delisted),
+            ]
+        );
+        assert_eq!(
+            rejection(engine.ingest(order(Operation::CREATE, "b3", "99", Side::BUY))),
+            Some(RejectReason::PAIR_DELISTED)
+        );
+        assert!(engine.resume("BTC/USDC").is_err(), "Delisting is final");
+        assert!(matches!(
+            engine.halt("ETH/USDC"),
+            Err(EngineError::UnknownInstrument { .. })
+        ));
+        let statuses = |engine: &MatcherEngine| {
+            let status = engine.instrument("BTC/USDC").map(Instrument::status);
+            let orders =
+                ["b1", "b2", "s1", "s3"].map(|id| engine.order_status(id).map(|s| s.status));
+            (status, orders, engine.sequencer.seq)
+        };
+        let before = statuses(&engine);
+        drop(engine);
+
+        let mut replayed = MatcherEngine::new();
+        replayed.set_registry(registry);
+        replayed.open_journal(&path).unwrap();
+        assert_eq!(statuses(&replayed), before);
+        assert_eq!(before.0, Some(TradingStatus::DELISTED));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    // ### Test 32: Amending A Post-Only Order Never Takes Liquidity
+    #[test]
+    fn test_post_only_amend_never_takes() {
+        let mut book = OrderBook::new("BTCUSD".to_string());
+        book.set_rules(InstrumentRules {
+            tick_size: Some(Decimal::new(5, 1)),
+            ..InstrumentRules::default()
+        });
+        seed_asks(&mut book, &[("ask1", "1", "100")]);
+        for (id, on_cross) in [("po1", CrossAction::REJECT), ("po2", CrossAction::SLIDE)] {
+            let mut bid =
+                create_raw_order(Operation::CREATE, "mm", "1", id, "BTCUSD", "98", Side::BUY);
+            bid.post_only = true;
+            bid.on_cross = on_cross;
+            book.process(bid).unwrap();
+        }
+        book.reports.clear();
+
+        for id in ["po1", "po2"] {
+            book.process(create_raw_order(
+                Operation::MODIFY,
+                "mm",
+                "1",
+                id,
+                "BTCUSD",
+                "101",
+                Side::BUY,
+            ))
+            .unwrap();
+        }
+
+        assert!(book.trades.is_empty(), "An amend must not cross the ask");
+        let reports: Vec<_> = book
+            .reports
+            .iter()
+            .map(|r| {
+                (
+                    r.order_id.as_str(),
+                    r.exec_type,
+                    r.reason,
+                    r.price.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reports,
+            [
+                (
+                    "po1",
+                    ExecType::REJECT,
+                    Some(ReasonCode::POST_ONLY_WOULD_CROSS),
+                    Some("101")
+                ),
+                (
+                    "po2",
+                    ExecType::REPLACE,
+                    Some(ReasonCode::POST_ONLY_REPRICED),
+                    Some("99.5")
+                ),
+            ]
+        );
+        let normalized = book.normalize();
+        assert_eq!(normalized.bids.len(), 1);
+        assert_eq!(normalized.bids[0].id, "po2");
+        assert_eq!(normalized.bids[0].price, "99.5");
+        assert_eq!(normalized.asks[0].remaining, "1");
+    }
+
+    // ### Test 33: FOK Does Not Count Liquidity STP Would Not Trade With
+    #[test]
+    fn test_fok_skips_self_owned_liquidity() {
+        let own_last = [("maker", "other", "1"), ("acc1", "own", "1")];
+        let own_first = [("acc1", "own", "1"), ("maker", "other", "2")];
+        for (mode, asks) in [
+            (StpMode::CANCEL_OLDEST, own_last),
+            (StpMode::DECREMENT_AND_CANCEL, own_last),
+            (StpMode::CANCEL_NEWEST, own_first),
+        ] {
+            let mut book = OrderBook::new("BTCUSD".to_string());
+            book.stp_mode = Some(mode);
+            for (account, id, amount) in asks {
+                book.process(create_raw_order(
+                    Operation::CREATE,
+                    account,
+                    amount,
+                    id,
+                    "BTCUSD",
+                    "100",
+                    Side::SELL,
+                ))
+                .unwrap();
+            }
+            let mut fok = create_raw_order(
+                Operation::CREATE,
+                "acc1",
+                "2",
+                "fok1",
+                "BTCUSD",
+                "100",
+                Side::BUY,
+            );
+            fok.time_in_force = TimeInForce::FOK;
+            book.process(fok).unwrap();
+
+            assert!(book.trades.is_empty(), "{mode:?}: FOK must not part-fill");
+            assert_eq!(
+                book.order_status("fok1").unwrap().status,
+                OrderStatus::CANCELLED
+            );
+            assert_eq!(book.normalize().asks.len(), 2, "{mode:?}: book untouched");
+        }
+    }
+
+    // ### Test 34: Closed Orders Are Forgotten Oldest First Past The Retention
+    #[test]
+    fn test_closed_order_retention() {
+        let mut engine = MatcherEngine::new();
+        engine.set_closed_retention(Some(2));
+        for id in ["o1", "o2", "o3"] {
+            let mut ioc = create_raw_order(
+                Operation::CREATE,
+                "acc1",
+                "1",
+                id,
+                "BTCUSD",
+                "100",
+                Side::BUY,
+            );
+            ioc.time_in_force = TimeInForce::IOC;
+            engine.ingest(ioc).unwrap();
+        }
+        assert!(engine.order_status("o1").is_none(), "Oldest is forgotten");
+        assert_eq!(
+            engine.order_status("o3").unwrap().status,
+            OrderStatus::CANCELLED
+        );
+        let reused = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "o1",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        engine.ingest(reused).unwrap();
+        let duplicate = create_raw_order(
+            Operation::CREATE,
+            "acc1",
+            "1",
+            "o2",
+            "BTCUSD",
+            "100",
+            Side::BUY,
+        );
+        assert!(
+            engine.ingest(duplicate).is_err(),
+            "Retained ids still clash"
+        );
+
+        let book = &engine.books["BTCUSD"];
+        let restored = OrderBook::from_snapshot(book.snapshot());
+        assert_eq!(restored.closed_ids, ["o2", "o3"]);
+        assert_eq!(restored.closed.len(), 2);
+    }
+}
@@ -1,632 +1,617 @@
+use backend_rust_task::{
+    BOOK_ROW_HEADER, DepthUpdate, EngineError, ExecutionReport, InstrumentRegistry, JournalEntry,
+    MatcherEngine, Order, RawOrder, RejectReason, SystemClock, TRADE_HEADER, Trade,
+};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
-use std::cmp::{Ordering, Reverse};
-use std::collections::{BinaryHeap, HashMap};
-use std::fs;
-use std::str::FromStr;
-
-#[derive(Deserialize)]
-pub enum Operation {
-    CREATE,
-    DELETE,
+use serde::Serialize;
+use serde_json::Value;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::ExitCode;
+
+#[derive(Parser)]
+#[command(
+    about = "Matches a batch of orders into order books and trades",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    /// Without a subcommand, runs `match` with these.
+    #[command(flatten)]
+    run: MatchArgs,
 }
 
-#[derive(Deserialize, Serialize, Clone, Eq, PartialEq)]
-pub enum Side {
-    BUY,
-    SELL,
+#[derive(Subcommand)]
+enum Command {
+    /// Match orders at the `timestamp` recorded on each, write the output
+    /// and exit non-zero if any row is rejected.
+    Match(MatchArgs),
+    /// Match orders and exit non-zero if any row is rejected. Writes nothing.
+    Validate(InputArgs),
+    /// Match orders or an engine journal as they were first matched, or
+    /// check a rerun against recorded output.
+    Replay(ReplayArgs),
+    /// Match newline-delimited orders as they arrive, writing each trade as
+    /// an NDJSON line as soon as it happens.
+    Stream(StreamArgs),
 }
 
-#[derive(Deserialize)]
-pub struct RawOrder {
-    type_op: Operation,
-    account_id: String,
-    amount: String,
-    order_id: String,
-    pair: String,
-    limit_price: String,
-    side: Side,
+#[derive(Args)]
+struct InputArgs {
+    /// Orders file, or `-` for stdin.
+    #[arg(short, long, default_value = "orders.json")]
+    input: String,
+    /// Only process orders for this pair; may be repeated.
+    #[arg(short, long = "pair")]
+    pairs: Vec<String>,
+    /// JSON file of the pairs to list, each with its trading rules: tick and
+    /// lot size, amount limits and minimum notional. Orders for any other
+    /// pair are rejected.
+    #[arg(long, value_name = "FILE")]
+    instruments: Option<String>,
 }
 
-#[derive(Clone, Eq, PartialEq)]
-pub struct BookOrder {
-    id: String,
-    account: String,
-    side: Side,
-    pair: String,
-    price: Decimal,
-    remaining: Decimal,
-    ts: u64,
+/// File format, picked by extension unless given explicitly.
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    Json,
+    Csv,
 }
 
-#[derive(Serialize, Clone)]
-pub struct Trade {
-    pair: String,
-    #[serde(rename = "buyOrderId")]
-    buy_order_id: String,
-    #[serde(rename = "sellOrderId")]
-    sell_order_id: String,
-    price: String,
-    amount: String,
-    ts: u64,
+impl Format {
+    fn of(path: &str) -> Format {
+        match Path::new(path).extension() {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Format::Csv,
+            _ => Format::Json,
+        }
+    }
 }
 
-#[derive(Serialize)]
-pub struct Order {
-    pair: String,
-    bids: Vec<Bid>,
-    asks: Vec<Ask>,
+#[derive(Args)]
+struct RunArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    /// Directory for the output files, or `-` for a single JSON document on
+    /// stdout.
+    #[arg(short, long, default_value = ".")]
+    output: String,
+    /// Format of the book and trades files; defaults to the input's.
+    /// Execution reports are always JSON.
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Write compact instead of pretty-printed JSON.
+    #[arg(long)]
+    compact: bool,
+    /// Also write `depth.json`, the totals of the best LEVELS price levels
+    /// per side. Always JSON.
+    #[arg(long, value_name = "LEVELS")]
+    depth: Option<usize>,
+    /// Group depth prices into buckets of this size, e.g. 1, 10 or 100.
+    #[arg(long, requires = "depth", value_parser = parse_grouping)]
+    grouping: Option<Decimal>,
 }
 
-#[derive(Serialize)]
-pub struct Bid {
-    id: String,
-    price: String,
-    remaining: String,
-    account: String,
+fn parse_grouping(value: &str) -> Result<Decimal, String> {
+    match value.parse::<Decimal>() {
+        Ok(grouping) if grouping > Decimal::ZERO => Ok(grouping),
+        _ => Err(format!("{value} is not a positive decimal")),
+    }
 }
 
-#[derive(Serialize)]
-pub struct Ask {
-    id: String,
-    price: String,
-    remaining: String,
-    account: String,
+#[derive(Args)]
+struct MatchArgs {
+    #[command(flatten)]
+    run: RunArgs,
+    /// Stamp orders with the host clock as they are read instead of their
+    /// recorded `timestamp`, so output differs from run to run.
+    #[arg(long)]
+    live: bool,
 }
 
-#[derive(Eq, PartialEq)]
-struct BidBookOrder(BookOrder);
-
-impl Ord for BidBookOrder {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.0
-            .price
-            .cmp(&other.0.price)
-            .then_with(|| other.0.ts.cmp(&self.0.ts))
-    }
+#[derive(Args)]
+struct ReplayArgs {
+    #[command(flatten)]
+    run: RunArgs,
+    /// The input is an engine journal rather than an orders file.
+    #[arg(long)]
+    journal: bool,
+    /// Instead of writing output, compare the trades and books produced with
+    /// the `trades.json` and `orderbook.json` recorded in DIR, and report
+    /// the first place they differ. Wall-clock `timestamp`s are left out, so
+    /// output of `match --live` can be verified too. Trade ids and sequence
+    /// numbers count across every pair, so the whole input is replayed and
+    /// `--pair` can't narrow it.
+    #[arg(long, value_name = "DIR", conflicts_with = "pairs")]
+    verify: Option<String>,
 }
 
-impl PartialOrd for BidBookOrder {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
-    }
+#[derive(Args)]
+struct StreamArgs {
+    /// NDJSON orders file, or `-` for stdin.
+    #[arg(short, long, default_value = "-")]
+    input: String,
+    /// Only process orders for this pair; may be repeated.
+    #[arg(short, long = "pair")]
+    pairs: Vec<String>,
+    /// NDJSON trades file, or `-` for stdout.
+    #[arg(short, long, default_value = "-")]
+    output: String,
+    /// Also write execution reports as NDJSON to this file.
+    #[arg(long)]
+    reports: Option<String>,
+    /// Stamp orders with their recorded `timestamp` instead of the host clock.
+    #[arg(long)]
+    replay: bool,
+    /// JSON file of the pairs to list, with their trading rules.
+    #[arg(long, value_name = "FILE")]
+    instruments: Option<String>,
+    /// Closed orders to remember per pair for duplicate id checks. Past it
+    /// the oldest are forgotten, so memory stays flat, and their ids can be
+    /// reused; 0 forgets them straight away.
+    #[arg(long, value_name = "ORDERS", default_value_t = 100_000)]
+    retain_closed: usize,
 }
 
-#[derive(Eq, PartialEq)]
-struct AskBookOrder(BookOrder);
-
-impl Ord for AskBookOrder {
-    fn cmp(&self, other: &Self) -> Ordering {
-        self.0
-            .price
-            .cmp(&other.0.price)
-            .then_with(|| self.0.ts.cmp(&other.0.ts))
-    }
+/// Everything a run produces, as written to stdout.
+#[derive(Serialize)]
+struct RunOutput<'a> {
+    orderbooks: &'a [Order],
+    trades: &'a [Trade],
+    #[serde(rename = "executionReports")]
+    execution_reports: &'a [ExecutionReport],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    depth: Option<&'a [DepthUpdate]>,
 }
 
-impl PartialOrd for AskBookOrder {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.cmp(other))
+/// Lists only the pairs in `path`, if one is given.
+fn load_instruments(engine: &mut MatcherEngine, path: Option<&str>) -> Result<(), EngineError> {
+    if let Some(path) = path {
+        engine.set_registry(InstrumentRegistry::load(path)?);
     }
+    Ok(())
 }
 
-struct OrderBook {
-    pair: String,
-    bids: BinaryHeap<BidBookOrder>,
-    asks: BinaryHeap<Reverse<AskBookOrder>>,
-    id_index: HashMap<String, BookOrder>,
-    seq: u64,
-    trades: Vec<Trade>,
+fn read_rows(input: &str) -> Result<Vec<Value>, EngineError> {
+    let text = if input == "-" {
+        io::read_to_string(io::stdin())?
+    } else {
+        fs::read_to_string(input)?
+    };
+    Ok(serde_json::from_str(&text)?)
 }
 
-impl OrderBook {
-    fn new(pair: String) -> Self {
-        OrderBook {
-            pair,
-            bids: BinaryHeap::new(),
-            asks: BinaryHeap::new(),
-            id_index: HashMap::new(),
-            seq: 0,
-            trades: Vec::new(),
-        }
+/// Feeds one row to the engine unless its pair is filtered out, and returns
+/// whether it was rejected. Rows are numbered from 1, like CSV lines.
+fn ingest_row(engine: &mut MatcherEngine, row: usize, value: Value, pairs: &[String]) -> bool {
+    let pair = value
+        .get("pair")
+        .and_then(|v| v.as_str())
+        .unwrap_or_default();
+    if !pairs.is_empty() && !pairs.iter().any(|p| p == pair) {
+        return false;
     }
-
-    fn process(&mut self, raw: RawOrder) {
-        if matches!(raw.type_op, Operation::DELETE) {
-            self.id_index.remove(&raw.order_id);
-            return;
-        }
-
-        let price = Decimal::from_str(&raw.limit_price).expect("Invalid limit_price");
-        let amount = Decimal::from_str(&raw.amount).expect("Invalid amount");
-        let mut order = BookOrder {
-            id: raw.order_id,
-            account: raw.account_id,
-            side: raw.side,
-            pair: raw.pair,
-            price,
-            remaining: amount,
-            ts: self.seq,
-        };
-        self.seq += 1;
-        self.match_order(&mut order);
-        if order.remaining > Decimal::ZERO {
-            self.add(order);
+    match engine.ingest_value(value) {
+        Ok(()) => false,
+        Err(err) => {
+            eprintln!("Row {row}: {err}");
+            true
         }
     }
+}
 
-    fn add(&mut self, order: BookOrder) {
-        self.id_index.insert(order.id.clone(), order.clone());
-        match order.side {
-            Side::BUY => self.bids.push(BidBookOrder(order)),
-            Side::SELL => self.asks.push(Reverse(AskBookOrder(order))),
+/// Feeds every row of the selected pairs to the engine and returns how many
+/// were rejected.
+fn run_rows(engine: &mut MatcherEngine, args: &InputArgs) -> Result<usize, EngineError> {
+    if Format::of(&args.input) == Format::Csv {
+        return run_csv_rows(engine, args);
+    }
+    let mut rejected = 0;
+    for (row, value) in (1..).zip(read_rows(&args.input)?) {
+        if ingest_row(engine, row, value, &args.pairs) {
+            rejected += 1;
         }
     }
+    Ok(rejected)
+}
 
-    fn match_order(&mut self, incoming: &mut BookOrder) {
-        let is_buy = matches!(incoming.side, Side::BUY);
-        if is_buy {
-            while incoming.remaining > Decimal::ZERO {
-                if let Some(mut best_order) = self.pop_active_top_asks() {
-                    if incoming.price < best_order.price {
-                        self.asks.push(Reverse(AskBookOrder(best_order)));
-                        break;
-                    }
-                    let trade_qty = incoming.remaining.min(best_order.remaining);
-                    let trade_price = best_order.price;
-                    let trade = Trade {
-                        pair: self.pair.clone(),
-                        buy_order_id: incoming.id.clone(),
-                        sell_order_id: best_order.id.clone(),
-                        price: trade_price.to_string(),
-                        amount: trade_qty.to_string(),
-                        ts: self.seq,
-                    };
-                    self.trades.push(trade);
-                    incoming.remaining -= trade_qty;
-                    best_order.remaining -= trade_qty;
-                    if best_order.remaining > Decimal::ZERO {
-                        self.id_index
-                            .insert(best_order.id.clone(), best_order.clone());
-                        self.asks.push(Reverse(AskBookOrder(best_order)));
-                    }
-                } else {
-                    break;
-                }
+/// CSV counterpart of `run_rows`: one order per record under a header row,
+/// with errors numbered by the line they were found on.
+fn run_csv_rows(engine: &mut MatcherEngine, args: &InputArgs) -> Result<usize, EngineError> {
+    let mut reader = csv::Reader::from_path(&args.input)?;
+    let headers = reader.headers()?.clone();
+    let column = |record: &csv::StringRecord, name: &str| {
+        let index = headers.iter().position(|h| h == name);
+        index
+            .and_then(|i| record.get(i))
+            .unwrap_or_default()
+            .to_string()
+    };
+    let mut rejected = 0;
+    for record in reader.records() {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let line = err.position().map_or(0, |p| p.line());
+                engine.reject("", "", None, RejectReason::MALFORMED_ORDER);
+                eprintln!("Line {line}: {}", EngineError::Csv(err));
+                rejected += 1;
+                continue;
             }
-        } else {
-            while incoming.remaining > Decimal::ZERO {
-                if let Some(mut best_order) = self.pop_active_top_bids() {
-                    if incoming.price > best_order.price {
-                        self.bids.push(BidBookOrder(best_order));
-                        break;
-                    }
-                    let trade_qty = incoming.remaining.min(best_order.remaining);
-                    let trade_price = best_order.price;
-                    let trade = Trade {
-                        pair: self.pair.clone(),
-                        buy_order_id: best_order.id.clone(),
-                        sell_order_id: incoming.id.clone(),
-                        price: trade_price.to_string(),
-                        amount: trade_qty.to_string(),
-                        ts: self.seq,
-                    };
-                    self.trades.push(trade);
-                    incoming.remaining -= trade_qty;
-                    best_order.remaining -= trade_qty;
-                    if best_order.remaining > Decimal::ZERO {
-                        self.id_index
-                            .insert(best_order.id.clone(), best_order.clone());
-                        self.bids.push(BidBookOrder(best_order));
-                    }
-                } else {
-                    break;
-                }
+        };
+        let line = record.position().map_or(0, |p| p.line());
+        let (order_id, pair) = (column(&record, "order_id"), column(&record, "pair"));
+        if !args.pairs.is_empty() && !args.pairs.contains(&pair) {
+            continue;
+        }
+        // Empty cells count as absent, so optional columns take their defaults.
+        let (names, cells): (csv::StringRecord, csv::StringRecord) = headers
+            .iter()
+            .zip(record.iter())
+            .filter(|(_, cell)| !cell.is_empty())
+            .unzip();
+        let result = match cells.deserialize::<RawOrder>(Some(&names)) {
+            Ok(raw) => engine.ingest(raw),
+            Err(err) => {
+                engine.reject(&pair, &order_id, None, RejectReason::MALFORMED_ORDER);
+                Err(EngineError::Csv(err))
             }
+        };
+        if let Err(err) = result {
+            eprintln!("Line {line}: {err}");
+            rejected += 1;
         }
     }
+    Ok(rejected)
+}
 
-    fn pop_active_top_asks(&mut self) -> Option<BookOrder> {
-        while let Some(Reverse(AskBookOrder(order))) = self.asks.pop() {
-            if let Some(active_order) = self.id_index.get(&order.id) {
-                if active_order.remaining > Decimal::ZERO {
-                    return Some(active_order.clone());
-                }
-            }
+/// One order to replay: a row of an orders file, or a journal entry that
+/// also carries the sequence number and time it was first applied at.
+enum Input {
+    Row(Value),
+    Entry(JournalEntry),
+}
+
+impl Input {
+    /// The order as JSON, for pointing at it in a report.
+    fn to_json(&self) -> String {
+        match self {
+            Input::Row(value) => value.to_string(),
+            Input::Entry(entry) => serde_json::to_string(entry).unwrap_or_default(),
         }
-        None
     }
+}
+
+fn read_inputs(args: &ReplayArgs) -> Result<Vec<Input>, EngineError> {
+    let input = &args.run.input.input;
+    Ok(if args.journal {
+        JournalEntry::read_all(input)?
+            .into_iter()
+            .map(Input::Entry)
+            .collect()
+    } else {
+        read_rows(input)?.into_iter().map(Input::Row).collect()
+    })
+}
 
-    fn pop_active_top_bids(&mut self) -> Option<BookOrder> {
-        while let Some(BidBookOrder(order)) = self.bids.pop() {
-            if let Some(active_order) = self.id_index.get(&order.id) {
-                if active_order.remaining > Decimal::ZERO {
-                    return Some(active_order.clone());
+/// `ingest_row` for either kind of input.
+fn feed(engine: &mut MatcherEngine, row: usize, input: Input, pairs: &[String]) -> bool {
+    match input {
+        Input::Row(value) => ingest_row(engine, row, value, pairs),
+        Input::Entry(entry) => {
+            if !pairs.is_empty() && !pairs.iter().any(|p| p == entry.pair()) {
+                return false;
+            }
+            match engine.replay_entry(entry) {
+                Ok(()) => false,
+                Err(err) => {
+                    eprintln!("Row {row}: {err}");
+                    true
                 }
             }
         }
-        None
     }
+}
 
-    fn normalize(&self) -> Order {
-        let mut bids: Vec<_> = self
-            .bids
-            .iter()
-            .filter_map(|BidBookOrder(order)| {
-                self.id_index
-                    .get(&order.id)
-                    .filter(|o| o.remaining > Decimal::ZERO).cloned()
-            })
-            .collect();
-        bids.sort_by(|a, b| b.price.cmp(&a.price).then_with(|| a.ts.cmp(&b.ts)));
-        let bids = bids
-            .into_iter()
-            .map(|order| Bid {
-                id: order.id.clone(),
-                price: order.price.to_string(),
-                remaining: order.remaining.to_string(),
-                account: order.account.clone(),
-            })
-            .collect();
-
-        let mut asks: Vec<_> = self
-            .asks
-            .iter()
-            .filter_map(|Reverse(AskBookOrder(order))| {
-                self.id_index
-                    .get(&order.id)
-                    .filter(|o| o.remaining > Decimal::ZERO).cloned()
-            })
-            .collect();
-        asks.sort_by(|a, b| a.price.cmp(&b.price).then_with(|| a.ts.cmp(&b.ts)));
-        let asks = asks
-            .into_iter()
-            .map(|order| Ask {
-                id: order.id.clone(),
-                price: order.price.to_string(),
-                remaining: order.remaining.to_string(),
-                account: order.account.clone(),
-            })
-            .collect();
-
-        Order {
-            pair: self.pair.clone(),
-            bids,
-            asks,
-        }
+/// `value` without its wall-clock `timestamp`, which a live run takes from
+/// the host clock and so never reproduces.
+fn without_timestamp(mut value: Value) -> Value {
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("timestamp");
     }
+    value
 }
 
-struct MatcherEngine {
-    books: HashMap<String, OrderBook>,
+fn show(value: Option<&Value>) -> String {
+    value.map_or_else(|| "none".to_string(), Value::to_string)
 }
 
-impl MatcherEngine {
-    fn new() -> Self {
-        MatcherEngine {
-            books: HashMap::new(),
+/// Replays the input and compares it, trade by trade and then level by
+/// level, with what was recorded in `dir`. Returns a description of the
+/// first difference, if any.
+fn verify(args: &ReplayArgs, dir: &Path) -> Result<Option<String>, EngineError> {
+    let recorded_trades: Vec<Value> =
+        serde_json::from_str::<Vec<Value>>(&fs::read_to_string(dir.join("trades.json"))?)?
+            .into_iter()
+            .map(without_timestamp)
+            .collect();
+    let recorded_books: Vec<Value> =
+        serde_json::from_str(&fs::read_to_string(dir.join("orderbook.json"))?)?;
+
+    let mut engine = MatcherEngine::new();
+    load_instruments(&mut engine, args.run.input.instruments.as_deref())?;
+    let mut matched = 0;
+    let mut last_input = None;
+    for (row, input) in (1..).zip(read_inputs(args)?) {
+        let shown = input.to_json();
+        feed(&mut engine, row, input, &args.run.input.pairs);
+        for trade in engine.drain().0 {
+            let trade = without_timestamp(serde_json::to_value(&trade)?);
+            let recorded = recorded_trades.get(matched);
+            if recorded != Some(&trade) {
+                return Ok(Some(format!(
+                    "Trade {matched} diverges at row {row}\n  \
+                     input:      {shown}\n  \
+                     replayed:   {trade}\n  \
+                     recorded:   {}\n  \
+                     last match: {}",
+                    show(recorded),
+                    show(matched.checked_sub(1).and_then(|i| recorded_trades.get(i))),
+                )));
+            }
+            matched += 1;
         }
+        last_input = Some((row, shown));
     }
-
-    fn ingest(&mut self, raw: RawOrder) {
-        let book = self
-            .books
-            .entry(raw.pair.clone())
-            .or_insert_with(|| OrderBook::new(raw.pair.clone()));
-        book.process(raw);
+    if let Some(missing) = recorded_trades.get(matched) {
+        let (row, shown) = last_input.unwrap_or_default();
+        return Ok(Some(format!(
+            "Trade {matched} was recorded but never replayed\n  \
+             last input: row {row}: {shown}\n  \
+             recorded:   {missing}\n  \
+             last match: {}",
+            show(matched.checked_sub(1).and_then(|i| recorded_trades.get(i))),
+        )));
     }
 
-    fn finish(&self) -> (Vec<Order>, Vec<Trade>) {
-        let orderbooks = self.books.values().map(|b| b.normalize()).collect();
-        let trades = self.books.values().flat_map(|b| b.trades.clone()).collect();
-        (orderbooks, trades)
+    let (books, _) = engine.finish();
+    let books = books
+        .iter()
+        .map(serde_json::to_value)
+        .collect::<Result<Vec<_>, _>>()?;
+    for i in 0..books.len().max(recorded_books.len()) {
+        let (replayed, recorded) = (books.get(i), recorded_books.get(i));
+        let pair = |book: Option<&Value>| book.map(|b| b["pair"].clone());
+        if pair(replayed) != pair(recorded) {
+            return Ok(Some(format!(
+                "Book {i} diverges\n  replayed pair: {}\n  recorded pair: {}",
+                show(pair(replayed).as_ref()),
+                show(pair(recorded).as_ref()),
+            )));
+        }
+        let (replayed, recorded) = (replayed.unwrap(), recorded.unwrap());
+        for side in ["bids", "asks"] {
+            let empty = Vec::new();
+            let orders = |book: &Value| {
+                let orders = book[side].as_array().unwrap_or(&empty);
+                orders
+                    .iter()
+                    .cloned()
+                    .map(without_timestamp)
+                    .collect::<Vec<_>>()
+            };
+            let (replayed, recorded) = (orders(replayed), orders(recorded));
+            for j in 0..replayed.len().max(recorded.len()) {
+                if replayed.get(j) != recorded.get(j) {
+                    return Ok(Some(format!(
+                        "Book {} {side} diverge at position {j}\n  \
+                         replayed:   {}\n  \
+                         recorded:   {}\n  \
+                         last match: {}",
+                        recorded_books[i]["pair"],
+                        show(replayed.get(j)),
+                        show(recorded.get(j)),
+                        show(j.checked_sub(1).and_then(|k| recorded.get(k))),
+                    )));
+                }
+            }
+        }
     }
+    Ok(None)
 }
 
-fn main() {
-    let input_path = "orders.json";
-    let orderbook_path = "orderbook.json";
-    let trades_path = "trades.json";
+/// Writes `header` and then one record per row, so even an empty file has
+/// its header. `header` must match the serialized field names of `T`.
+fn write_csv<T: Serialize>(
+    path: &Path,
+    header: &[&str],
+    rows: impl IntoIterator<Item = T>,
+) -> Result<(), EngineError> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_path(path)?;
+    writer.write_record(header)?;
+    for row in rows {
+        writer.serialize(row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
 
-    let input = fs::read_to_string(input_path).expect("Failed to read input file");
-    let raw_orders: Vec<RawOrder> =
-        serde_json::from_str(&input).expect("Failed to parse input JSON");
+fn open_writer(path: &str) -> Result<Box<dyn Write>, EngineError> {
+    Ok(if path == "-" {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    })
+}
 
-    let mut engine = MatcherEngine::new();
-    for raw in raw_orders {
-        engine.ingest(raw);
+fn write_ndjson<T: Serialize>(out: &mut dyn Write, items: &[T]) -> Result<(), EngineError> {
+    for item in items {
+        serde_json::to_writer(&mut *out, item)?;
+        out.write_all(b"\n")?;
     }
-    let (orderbooks, trades) = engine.finish();
-
-    fs::write(
-        orderbook_path,
-        serde_json::to_string_pretty(&orderbooks).unwrap(),
-    )
-    .expect("Failed to write orderbook");
-    fs::write(trades_path, serde_json::to_string_pretty(&trades).unwrap())
-        .expect("Failed to write trades");
+    out.flush()?;
+    Ok(())
 }
 
-// Unit Tests
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn create_raw_order(
-        type_op: Operation,
-        account_id: &str,
-        amount: &str,
-        order_id: &str,
-        pair: &str,
-        limit_price: &str,
-        side: Side,
-    ) -> RawOrder {
-        RawOrder {
-            type_op,
-            account_id: account_id.to_string(),
-            amount: amount.to_string(),
-            order_id: order_id.to_string(),
-            pair: pair.to_string(),
-            limit_price: limit_price.to_string(),
-            side,
+/// Reads NDJSON orders line by line and flushes what each one produced
+/// before reading the next, so memory stays flat apart from the books.
+fn stream(args: &StreamArgs) -> Result<(), EngineError> {
+    let mut engine = if args.replay {
+        MatcherEngine::new()
+    } else {
+        MatcherEngine::with_clock(Box::new(SystemClock))
+    };
+    engine.set_closed_retention(Some(args.retain_closed));
+    load_instruments(&mut engine, args.instruments.as_deref())?;
+    let input: Box<dyn BufRead> = if args.input == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(&args.input)?))
+    };
+    let mut trades_out = open_writer(&args.output)?;
+    let mut reports_out = args.reports.as_deref().map(open_writer).transpose()?;
+
+    for (row, line) in (1..).zip(input.lines()) {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(value) => {
+                ingest_row(&mut engine, row, value, &args.pairs);
+            }
+            Err(err) => {
+                engine.reject("", "", None, RejectReason::MALFORMED_ORDER);
+                eprintln!("Row {row}: {}", EngineError::Json(err));
+            }
+        }
+        let (trades, reports) = engine.drain();
+        write_ndjson(&mut trades_out, &trades)?;
+        if let Some(out) = reports_out.as_mut() {
+            write_ndjson(out, &reports)?;
         }
     }
+    Ok(())
+}
 
-    // ### Test 1: Adding Buy and Sell Orders
-    #[test]
-    fn test_add_orders() {
-        let mut book = OrderBook::new("BTCUSD".to_string());
-
-        let raw_buy = create_raw_order(
-            Operation::CREATE,
-            "acc1",
-            "10",
-            "order1",
-            "BTCUSD",
-            "100",
-            Side::BUY,
-        );
-        book.process(raw_buy);
-
-        let raw_sell = create_raw_order(
-            Operation::CREATE,
-            "acc2",
-            "10",
-            "order2",
-            "BTCUSD",
-            "101",
-            Side::SELL,
-        );
-        book.process(raw_sell);
-
-        let normalized = book.normalize();
-        assert_eq!(normalized.pair, "BTCUSD");
-        assert_eq!(normalized.bids.len(), 1, "Should have 1 bid");
-        assert_eq!(normalized.asks.len(), 1, "Should have 1 ask");
-        assert_eq!(normalized.bids[0].id, "order1");
-        assert_eq!(normalized.bids[0].price, "100");
-        assert_eq!(normalized.bids[0].remaining, "10");
-        assert_eq!(normalized.asks[0].id, "order2");
-        assert_eq!(normalized.asks[0].price, "101");
-        assert_eq!(normalized.asks[0].remaining, "10");
-    }
+fn to_json<T: Serialize>(value: &T, compact: bool) -> Result<String, EngineError> {
+    let json = if compact {
+        serde_json::to_string(value)?
+    } else {
+        serde_json::to_string_pretty(value)?
+    };
+    Ok(json)
+}
 
-    // ### Test 2: Deleting an Order
-    #[test]
-    fn test_delete_order() {
-        let mut book = OrderBook::new("BTCUSD".to_string());
-
-        let raw_buy = create_raw_order(
-            Operation::CREATE,
-            "acc1",
-            "10",
-            "order1",
-            "BTCUSD",
-            "100",
-            Side::BUY,
-        );
-        book.process(raw_buy);
-
-        let raw_delete = create_raw_order(
-            Operation::DELETE,
-            "acc1",
-            "0",
-            "order1",
-            "BTCUSD",
-            "0",
-            Side::BUY,
-        );
-        book.process(raw_delete);
-
-        let normalized = book.normalize();
-        assert_eq!(normalized.bids.len(), 0, "Bids should be empty");
-        assert_eq!(normalized.asks.len(), 0, "Asks should be empty");
+fn write_output(engine: &MatcherEngine, args: &RunArgs) -> Result<(), EngineError> {
+    let (orderbooks, trades) = engine.finish();
+    let reports = engine.reports();
+    let depth = args.depth.map(|levels| engine.depth(levels, args.grouping));
+    if args.output == "-" {
+        let output = RunOutput {
+            orderbooks: &orderbooks,
+            trades: &trades,
+            execution_reports: reports,
+            depth: depth.as_deref(),
+        };
+        println!("{}", to_json(&output, args.compact)?);
+        return Ok(());
     }
 
-    // ### Test 3: Matching Orders (Full Match)
-    #[test]
-    fn test_match_orders() {
-        let mut book = OrderBook::new("BTCUSD".to_string());
-
-        let raw_sell = create_raw_order(
-            Operation::CREATE,
-            "acc1",
-            "10",
-            "sell1",
-            "BTCUSD",
-            "100",
-            Side::SELL,
-        );
-        book.process(raw_sell);
-
-        let raw_buy = create_raw_order(
-            Operation::CREATE,
-            "acc2",
-            "5",
-            "buy1",
-            "BTCUSD",
-            "101",
-            Side::BUY,
-        );
-        book.process(raw_buy);
-
-        let normalized = book.normalize();
-        assert_eq!(normalized.bids.len(), 0, "No bids should remain");
-        assert_eq!(normalized.asks.len(), 1, "One ask should remain");
-        assert_eq!(normalized.asks[0].id, "sell1");
-        assert_eq!(normalized.asks[0].remaining, "5");
-
-        assert_eq!(book.trades.len(), 1, "Should have 1 trade");
-        let trade = &book.trades[0];
-        assert_eq!(trade.pair, "BTCUSD");
-        assert_eq!(trade.buy_order_id, "buy1");
-        assert_eq!(trade.sell_order_id, "sell1");
-        assert_eq!(
-            trade.price, "100",
-            "Trade price should be the resting ask price"
-        );
-        assert_eq!(trade.amount, "5");
+    let dir = Path::new(&args.output);
+    fs::create_dir_all(dir)?;
+    match args.format.unwrap_or(Format::of(&args.input.input)) {
+        Format::Json => {
+            fs::write(
+                dir.join("orderbook.json"),
+                to_json(&orderbooks, args.compact)?,
+            )?;
+            fs::write(dir.join("trades.json"), to_json(&trades, args.compact)?)?;
+        }
+        Format::Csv => {
+            write_csv(
+                &dir.join("orderbook.csv"),
+                &BOOK_ROW_HEADER,
+                orderbooks.iter().flat_map(Order::rows),
+            )?;
+            write_csv(&dir.join("trades.csv"), &TRADE_HEADER, &trades)?;
+        }
     }
-
-    // ### Test 4: Partial Matching
-    #[test]
-    fn test_partial_match() {
-        let mut book = OrderBook::new("BTCUSD".to_string());
-
-        let raw_buy = create_raw_order(
-            Operation::CREATE,
-            "acc1",
-            "10",
-            "buy1",
-            "BTCUSD",
-            "100",
-            Side::BUY,
-        );
-        book.process(raw_buy);
-
-        let raw_sell = create_raw_order(
-            Operation::CREATE,
-            "acc2",
-            "5",
-            "sell1",
-            "BTCUSD",
-            "100",
-            Side::SELL,
-        );
-        book.process(raw_sell);
-
-        let normalized = book.normalize();
-        assert_eq!(normalized.bids.len(), 1, "One bid should remain");
-        assert_eq!(normalized.bids[0].id, "buy1");
-        assert_eq!(normalized.bids[0].remaining, "5");
-        assert_eq!(normalized.asks.len(), 0, "No asks should remain");
-
-        assert_eq!(book.trades.len(), 1, "Should have 1 trade");
-        let trade = &book.trades[0];
-        assert_eq!(trade.buy_order_id, "buy1");
-        assert_eq!(trade.sell_order_id, "sell1");
-        assert_eq!(
-            trade.price, "100",
-            "Trade price should be the resting bid price"
-        );
-        assert_eq!(trade.amount, "5");
+    fs::write(
+        dir.join("execution_reports.json"),
+        to_json(&reports, args.compact)?,
+    )?;
+    if let Some(depth) = depth {
+        fs::write(dir.join("depth.json"), to_json(&depth, args.compact)?)?;
     }
+    Ok(())
+}
 
-    // ### Test 5: Multiple Orders at Same Price (Time Priority)
-    #[test]
-    fn test_multiple_orders_same_price() {
-        let mut book = OrderBook::new("BTCUSD".to_string());
-
-        let raw_buy1 = create_raw_order(
-            Operation::CREATE,
-            "acc1",
-            "5",
-            "buy1",
-            "BTCUSD",
-            "100",
-            Side::BUY,
-        );
-        book.process(raw_buy1);
-
-        let raw_buy2 = create_raw_order(
-            Operation::CREATE,
-            "acc2",
-            "5",
-            "buy2",
-            "BTCUSD",
-            "100",
-            Side::BUY,
-        );
-        book.process(raw_buy2);
-
-        let raw_sell = create_raw_order(
-            Operation::CREATE,
-            "acc3",
-            "10",
-            "sell1",
-            "BTCUSD",
-            "100",
-            Side::SELL,
-        );
-        book.process(raw_sell);
-
-        let normalized = book.normalize();
-        assert_eq!(normalized.bids.len(), 0, "No bids should remain");
-        assert_eq!(normalized.asks.len(), 0, "No asks should remain");
-
-        assert_eq!(book.trades.len(), 2, "Should have 2 trades");
-
-        assert_eq!(book.trades[0].buy_order_id, "buy1");
-        assert_eq!(book.trades[0].sell_order_id, "sell1");
-        assert_eq!(book.trades[0].amount, "5");
-        assert_eq!(book.trades[0].price, "100");
-
-        assert_eq!(book.trades[1].buy_order_id, "buy2");
-        assert_eq!(book.trades[1].sell_order_id, "sell1");
-        assert_eq!(book.trades[1].amount, "5");
-        assert_eq!(book.trades[1].price, "100");
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("{err}");
+            ExitCode::FAILURE
+        }
     }
+}
 
-    // ### Test 6: Multiple Trading Pairs
-    #[test]
-    fn test_multiple_pairs() {
-        let mut engine = MatcherEngine::new();
-
-        let raw_btc = create_raw_order(
-            Operation::CREATE,
-            "acc1",
-            "10",
-            "order1",
-            "BTCUSD",
-            "100",
-            Side::BUY,
-        );
-        engine.ingest(raw_btc);
-
-        let raw_eth = create_raw_order(
-            Operation::CREATE,
-            "acc2",
-            "20",
-            "order2",
-            "ETHUSD",
-            "200",
-            Side::SELL,
-        );
-        engine.ingest(raw_eth);
-
-        let (orderbooks, trades) = engine.finish();
-        assert_eq!(orderbooks.len(), 2, "Should have 2 order books");
-        assert_eq!(trades.len(), 0, "No trades should occur");
-
-        let btc_book = orderbooks.iter().find(|ob| ob.pair == "BTCUSD").unwrap();
-        assert_eq!(btc_book.bids.len(), 1, "BTCUSD should have 1 bid");
-        assert_eq!(btc_book.bids[0].id, "order1");
-        assert_eq!(btc_book.asks.len(), 0);
-
-        let eth_book = orderbooks.iter().find(|ob| ob.pair == "ETHUSD").unwrap();
-        assert_eq!(eth_book.bids.len(), 0);
-        assert_eq!(eth_book.asks.len(), 1, "ETHUSD should have 1 ask");
-        assert_eq!(eth_book.asks[0].id, "order2");
+fn run(cli: Cli) -> Result<ExitCode, EngineError> {
+    match cli.command.unwrap_or(Command::Match(cli.run)) {
+        Command::Match(MatchArgs { run: args, live }) => {
+            let mut engine = if live {
+                MatcherEngine::with_clock(Box::new(SystemClock))
+            } else {
+                MatcherEngine::new()
+            };
+            load_instruments(&mut engine, args.input.instruments.as_deref())?;
+            let rejected = run_rows(&mut engine, &args.input)?;
+            write_output(&engine, &args)?;
+            if rejected > 0 {
+                eprintln!("{rejected} row(s) rejected");
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Replay(args) => {
+            if let Some(dir) = &args.verify {
+                if !args.journal && Format::of(&args.run.input.input) == Format::Csv {
+                    eprintln!("--verify reads JSON orders or a journal, not CSV");
+                    return Ok(ExitCode::FAILURE);
+                }
+                if let Some(divergence) = verify(&args, Path::new(dir))? {
+                    eprintln!("{divergence}");
+                    return Ok(ExitCode::FAILURE);
+                }
+                eprintln!("Replay matches {dir}");
+            } else if args.journal {
+                let mut engine = MatcherEngine::new();
+                load_instruments(&mut engine, args.run.input.instruments.as_deref())?;
+                for (row, input) in (1..).zip(read_inputs(&args)?) {
+                    feed(&mut engine, row, input, &args.run.input.pairs);
+                }
+                write_output(&engine, &args.run)?;
+            } else {
+                let mut engine = MatcherEngine::new();
+                load_instruments(&mut engine, args.run.input.instruments.as_deref())?;
+                run_rows(&mut engine, &args.run.input)?;
+                write_output(&engine, &args.run)?;
+            }
+        }
+        Command::Validate(args) => {
+            let mut engine = MatcherEngine::new();
+            load_instruments(&mut engine, args.instruments.as_deref())?;
+            let rejected = run_rows(&mut engine, &args)?;
+            eprintln!("{rejected} row(s) rejected");
+            if rejected > 0 {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+        Command::Stream(args) => stream(&args)?,
     }
+    Ok(ExitCode::SUCCESS)
 }
@@ -0,0 +1,82 @@
+//! Point-in-time copies of an engine's books, one JSON file per snapshot,
+//! named by the sequence number they were taken at so the latest sorts last.
+
+use crate::{BookOrder, EngineError, StpMode, TradingStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Format of the snapshot files this build writes and reads.
+pub(crate) const SNAPSHOT_VERSION: u32 = 1;
+
+/// Everything needed to carry on exactly where the engine was.
+#[derive(Serialize, Deserialize)]
+pub(crate) struct EngineSnapshot {
+    pub(crate) version: u32,
+    /// Next sequence number; journal entries from here on are not in it.
+    pub(crate) seq: u64,
+    pub(crate) trade_id: u64,
+    pub(crate) now: u64,
+    pub(crate) stp_mode: Option<StpMode>,
+    /// Status of each listed pair; rules come from the registry.
+    #[serde(default)]
+    pub(crate) statuses: BTreeMap<String, TradingStatus>,
+    pub(crate) books: Vec<BookSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub(crate) struct BookSnapshot {
+    pub(crate) pair: String,
+    pub(crate) stp_mode: Option<StpMode>,
+    pub(crate) now: u64,
+    pub(crate) depth_seq: u64,
+    /// Bids best first, then asks best first, each level in time priority.
+    pub(crate) resting: Vec<BookOrder>,
+    /// Terminal orders, by `ts`, kept for status queries.
+    pub(crate) closed: Vec<BookOrder>,
+}
+
+impl EngineSnapshot {
+    /// Writes the snapshot into `dir` under a name that sorts by `seq`.
+    /// The file is written aside and renamed, so a crash never leaves a
+    /// half-written snapshot in its place, and the directory is synced so
+    /// the rename itself survives one.
+    pub(crate) fn write(&self, dir: &Path) -> Result<PathBuf, EngineError> {
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("snapshot-{:020}.json", self.seq));
+        let partial = path.with_extension("json.partial");
+        fs::write(&partial, serde_json::to_vec(self)?)?;
+        fs::File::open(&partial)?.sync_all()?;
+        fs::rename(&partial, &path)?;
+        fs::File::open(dir)?.sync_all()?;
+        Ok(path)
+    }
+
+    /// Reads the newest snapshot in `dir`; `None` if there is none yet.
+    pub(crate) fn latest(dir: &Path) -> Result<Option<EngineSnapshot>, EngineError> {
+        if !dir.exists() {
+            return Ok(None);
+        }
+        let mut paths: Vec<PathBuf> = fs::read_dir(dir)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<Result<_, _>>()?;
+        paths.retain(|path| {
+            path.extension().is_some_and(|ext| ext == "json")
+                && path
+                    .file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with("snapshot-"))
+        });
+        paths.sort();
+        let Some(path) = paths.last() else {
+            return Ok(None);
+        };
+        // The version is checked before the rest, whose layout depends on it.
+        let value: serde_json::Value = serde_json::from_slice(&fs::read(path)?)?;
+        let version = value["version"].as_u64().unwrap_or_default();
+        if version != u64::from(SNAPSHOT_VERSION) {
+            return Err(EngineError::UnsupportedSnapshot { version });
+        }
+        Ok(Some(serde_json::from_value(value)?))
+    }
+}
@@ -0,0 +1,59 @@
+//! Uses the engine through its public API only, as an embedding service would.
+
+use backend_rust_task::{
+    EngineError, ExecType, MatcherEngine, OrderStatus, RawOrder, RejectReason,
+};
+
+fn order(order_id: &str, account_id: &str, side: &str, amount: &str, price: &str) -> RawOrder {
+    serde_json::from_value(serde_json::json!({
+        "type_op": "CREATE",
+        "account_id": account_id,
+        "amount": amount,
+        "order_id": order_id,
+        "pair": "BTC/USDC",
+        "limit_price": price,
+        "side": side,
+    }))
+    .unwrap()
+}
+
+#[test]
+fn engine_is_usable_as_a_library() {
+    let mut engine = MatcherEngine::default();
+    engine
+        .ingest(order("1", "maker", "SELL", "2", "100"))
+        .unwrap();
+    engine
+        .ingest(order("2", "taker", "BUY", "0.5", "101"))
+        .unwrap();
+
+    let err = engine
+        .ingest(order("2", "taker", "BUY", "1", "99"))
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        EngineError::Rejected {
+            reason: RejectReason::DUPLICATE_ORDER_ID,
+            ..
+        }
+    ));
+
+    let (books, trades) = engine.finish();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].buy_order_id(), "2");
+    assert_eq!(trades[0].sell_order_id(), "1");
+    assert_eq!(trades[0].price(), "100");
+    assert_eq!(trades[0].amount(), "0.5");
+
+    assert_eq!(books[0].pair(), "BTC/USDC");
+    assert!(books[0].bids().is_empty());
+    assert_eq!(books[0].asks()[0].remaining(), "1.5");
+
+    let state = engine.order_status("1").unwrap();
+    assert_eq!(state.status(), OrderStatus::PARTIALLY_FILLED);
+    assert_eq!(state.avg_price(), Some("100"));
+
+    let last = engine.reports().last().unwrap();
+    assert_eq!(last.exec_type(), ExecType::REJECT);
+    assert_eq!(last.order_id(), "2");
+}
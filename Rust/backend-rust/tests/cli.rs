@@ -0,0 +1,306 @@
+//! Drives the binary's subcommands through stdin and stdout.
+
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+const ORDERS: &str = r#"[
+  {"type_op":"CREATE","account_id":"1","amount":"1","order_id":"1","pair":"BTC/USDC","limit_price":"100","side":"SELL"},
+  {"type_op":"CREATE","account_id":"2","amount":"1","order_id":"2","pair":"ETH/USDC","limit_price":"10","side":"SELL"},
+  {"type_op":"CREATE","account_id":"3","amount":"1","order_id":"3","pair":"BTC/USDC","limit_price":"100","side":"BUY"}
+]"#;
+
+fn run(args: &[&str], stdin: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_backend-rust-task"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run binary");
+    // A run that fails on its arguments may exit before reading stdin.
+    if let Err(err) = child.stdin.take().unwrap().write_all(stdin.as_bytes()) {
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe, "{err}");
+    }
+    child.wait_with_output().expect("Failed to wait for binary")
+}
+
+#[test]
+fn replay_to_stdout_keeps_only_selected_pair() {
+    let output = run(
+        &[
+            "replay",
+            "-i",
+            "-",
+            "-o",
+            "-",
+            "--compact",
+            "-p",
+            "BTC/USDC",
+        ],
+        ORDERS,
+    );
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(stdout.lines().count(), 1, "Compact output is one line");
+    let doc: serde_json::Value = serde_json::from_str(&stdout).unwrap();
+    let books = doc["orderbooks"].as_array().unwrap();
+    assert_eq!(books.len(), 1);
+    assert_eq!(books[0]["pair"], "BTC/USDC");
+    assert_eq!(doc["trades"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn match_is_reproducible_and_exit_code_follows_rejections() {
+    let args = ["match", "-i", "-", "-o", "-", "--compact"];
+    let first = run(&args, ORDERS);
+    assert!(first.status.success());
+    assert_eq!(
+        first.stdout,
+        run(&args, ORDERS).stdout,
+        "Same input, same output"
+    );
+    assert_eq!(
+        first.stdout,
+        run(&args[1..], ORDERS).stdout,
+        "match is the default"
+    );
+
+    let bad = ORDERS.replace(
+        r#""amount":"1","order_id":"2""#,
+        r#""amount":"0","order_id":"2""#,
+    );
+    let output = run(&args, &bad);
+    assert!(!output.status.success());
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(
+        doc["trades"].as_array().unwrap().len(),
+        1,
+        "Output is still written"
+    );
+
+    let output = run(&[&args[..], &["--live"]].concat(), ORDERS);
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_ne!(
+        doc["trades"][0]["timestamp"], 0,
+        "Stamped by the host clock"
+    );
+}
+
+#[test]
+fn validate_exit_code_follows_rejections() {
+    assert!(run(&["validate", "-i", "-"], ORDERS).status.success());
+
+    let bad = ORDERS.replace(
+        r#""amount":"1","order_id":"2""#,
+        r#""amount":"0","order_id":"2""#,
+    );
+    let output = run(&["validate", "-i", "-"], &bad);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Row 2: order 2 rejected"), "{stderr}");
+
+    let output = run(&["validate", "-i", "missing.json"], "");
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.starts_with("I/O error: "), "{stderr}");
+}
+
+#[test]
+fn stream_writes_each_trade_as_an_ndjson_line() {
+    let orders: serde_json::Value = serde_json::from_str(ORDERS).unwrap();
+    let mut ndjson: String = orders
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|order| format!("{order}\n\n"))
+        .collect();
+    ndjson.push_str("not json\n");
+    let output = run(&["stream", "--replay"], &ndjson);
+    assert!(output.status.success());
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let trades: Vec<serde_json::Value> = stdout
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0]["buyOrderId"], "3");
+    assert_eq!(trades[0]["sellOrderId"], "1");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Row 7"), "Bad line is reported: {stderr}");
+}
+
+#[test]
+fn csv_orders_produce_csv_trades_and_line_numbered_errors() {
+    let dir = std::env::temp_dir().join(format!("cli-csv-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let input = dir.join("orders.csv");
+    std::fs::write(
+        &input,
+        "type_op,account_id,amount,order_id,pair,limit_price,side,time_in_force\n\
+         CREATE,1,1.50,1,BTC/USDC,100.00,SELL,\n\
+         CREATE,2,oops,2,BTC/USDC,100.00,BUY,GTC\n\
+         CREATE,3,0.25,3\n\
+         CREATE,3,0.25,4,BTC/USDC,100.00,BUY,IOC\n",
+    )
+    .unwrap();
+
+    let output = run(
+        &[
+            "replay",
+            "-i",
+            input.to_str().unwrap(),
+            "-o",
+            dir.to_str().unwrap(),
+        ],
+        "",
+    );
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Line 3: order 2 rejected"), "{stderr}");
+    assert!(stderr.contains("Line 4: CSV error"), "{stderr}");
+
+    let trades = std::fs::read_to_string(dir.join("trades.csv")).unwrap();
+    assert_eq!(
+        trades,
+        "tradeId,pair,buyOrderId,sellOrderId,price,amount,ts,timestamp\n\
+         1,BTC/USDC,4,1,100.00,0.25,7,0\n"
+    );
+    let book = std::fs::read_to_string(dir.join("orderbook.csv")).unwrap();
+    assert_eq!(
+        book,
+        "pair,side,id,price,remaining,account,ts,timestamp\n\
+         BTC/USDC,SELL,1,100.00,1.25,1,1,0\n"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn depth_groups_best_levels_into_buckets() {
+    let orders = r#"[
+      {"type_op":"CREATE","account_id":"1","amount":"1","order_id":"1","pair":"BTC/USDC","limit_price":"101","side":"SELL"},
+      {"type_op":"CREATE","account_id":"1","amount":"2","order_id":"2","pair":"BTC/USDC","limit_price":"109","side":"SELL"},
+      {"type_op":"CREATE","account_id":"1","amount":"1","order_id":"3","pair":"BTC/USDC","limit_price":"115","side":"SELL"}
+    ]"#;
+    let args = ["replay", "-i", "-", "-o", "-", "--depth", "1"];
+    let output = run(&[&args[..], &["--grouping", "10"]].concat(), orders);
+    assert!(output.status.success());
+    let doc: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    let asks = doc["depth"][0]["asks"].as_array().unwrap();
+    assert_eq!(asks.len(), 1, "Only the best bucket");
+    assert_eq!(asks[0]["price"], "110");
+    assert_eq!(asks[0]["quantity"], "3");
+    assert_eq!(asks[0]["orders"], 2);
+
+    let output = run(&[&args[..], &["--grouping", "0"]].concat(), orders);
+    assert!(!output.status.success(), "Grouping must be positive");
+}
+
+#[test]
+fn replay_verify_points_at_the_first_divergence() {
+    let dir = std::env::temp_dir().join(format!("cli-verify-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let recorded = dir.to_str().unwrap();
+    assert!(
+        run(&["replay", "-i", "-", "-o", recorded], ORDERS)
+            .status
+            .success()
+    );
+
+    let verify = ["replay", "-i", "-", "--verify", recorded];
+    let output = run(&verify, ORDERS);
+    assert!(output.status.success(), "A rerun matches its own output");
+    let output = run(&[&verify[..], &["-p", "BTC/USDC"]].concat(), ORDERS);
+    assert!(!output.status.success(), "Every pair is verified");
+
+    let trades = std::fs::read_to_string(dir.join("trades.json")).unwrap();
+    std::fs::write(
+        dir.join("trades.json"),
+        trades.replace(r#""price": "100""#, r#""price": "101""#),
+    )
+    .unwrap();
+    let output = run(&verify, ORDERS);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Trade 0 diverges at row 3"), "{stderr}");
+    assert!(
+        stderr.contains(r#""order_id":"3""#),
+        "Shows the input: {stderr}"
+    );
+    assert!(stderr.contains(r#""price":"101""#), "{stderr}");
+    std::fs::write(dir.join("trades.json"), trades).unwrap();
+
+    let book = std::fs::read_to_string(dir.join("orderbook.json")).unwrap();
+    std::fs::write(
+        dir.join("orderbook.json"),
+        book.replace(r#""remaining": "1""#, r#""remaining": "0.5""#),
+    )
+    .unwrap();
+    let output = run(&verify, ORDERS);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains(r#"Book "ETH/USDC" asks diverge at position 0"#),
+        "{stderr}"
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn replay_verifies_the_output_of_a_live_match() {
+    let dir = std::env::temp_dir().join(format!("cli-live-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let recorded = dir.to_str().unwrap();
+    let output = run(&["match", "--live", "-i", "-", "-o", recorded], ORDERS);
+    assert!(output.status.success());
+    let trades = std::fs::read_to_string(dir.join("trades.json")).unwrap();
+    assert!(
+        !trades.contains(r#""timestamp": 0"#),
+        "Stamped live: {trades}"
+    );
+
+    let output = run(&["replay", "-i", "-", "--verify", recorded], ORDERS);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{stderr}");
+    assert!(stderr.contains("Replay matches"), "{stderr}");
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn instruments_file_lists_pairs_and_rejects_off_tick_prices() {
+    let dir = std::env::temp_dir().join(format!("cli-instruments-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let instruments = dir.join("instruments.json");
+    std::fs::write(
+        &instruments,
+        r#"{"BTC/USDC": {"tick_size": "1", "min_notional": "50"}, "ETH/USDC": {}}"#,
+    )
+    .unwrap();
+    let args = ["validate", "-i", "-", "--instruments"];
+    let args = [&args[..], &[instruments.to_str().unwrap()]].concat();
+    assert!(run(&args, ORDERS).status.success());
+
+    let off_tick = ORDERS.replace(r#""limit_price":"100""#, r#""limit_price":"100.5""#);
+    let output = run(&args, &off_tick);
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("Row 1: order 1 rejected: price is not a multiple"),
+        "{stderr}"
+    );
+
+    std::fs::write(&instruments, r#"{"BTC/USDC": {}}"#).unwrap();
+    let output = run(&args, ORDERS);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("order 2 rejected: unknown pair"),
+        "{stderr}"
+    );
+
+    std::fs::write(&instruments, r#"{"BTC/USDC": {"tick_size": "0"}}"#).unwrap();
+    assert!(!run(&args, ORDERS).status.success(), "Zero tick is refused");
+    let _ = std::fs::remove_dir_all(&dir);
+}
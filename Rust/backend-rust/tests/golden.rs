@@ -0,0 +1,46 @@
+//! Runs the binary over the bundled `orders.json` and compares its output
+//! byte-for-byte with the committed golden files. Regenerate them with
+//! `cargo run`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+const GOLDEN_FILES: [&str; 3] = ["orderbook.json", "trades.json", "execution_reports.json"];
+
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("{name}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Failed to create scratch dir");
+    dir
+}
+
+#[test]
+fn bundled_orders_match_golden_files() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let work_dir = scratch_dir("golden");
+    fs::copy(
+        manifest_dir.join("orders.json"),
+        work_dir.join("orders.json"),
+    )
+    .expect("Failed to copy orders.json");
+
+    // Twice, so run-to-run ordering differences would show up as well.
+    for _ in 0..2 {
+        let status = Command::new(env!("CARGO_BIN_EXE_backend-rust-task"))
+            .current_dir(&work_dir)
+            .status()
+            .expect("Failed to run binary");
+        assert!(status.success());
+
+        for file in GOLDEN_FILES {
+            let expected = fs::read(manifest_dir.join(file)).expect("Missing golden file");
+            let actual = fs::read(work_dir.join(file)).expect("Missing output file");
+            assert!(
+                expected == actual,
+                "{file} differs from the committed golden file"
+            );
+        }
+    }
+    let _ = fs::remove_dir_all(&work_dir);
+}
@@ -0,0 +1,365 @@
+//! Drives the HTTP server binary over a real socket with plain HTTP/1.1.
+
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::{Child, ChildStderr, Command, Stdio};
+
+/// Kills the server when the test ends, pass or fail.
+struct Server {
+    child: Child,
+    addr: String,
+    /// Held open so the server can keep logging.
+    _stderr: BufReader<ChildStderr>,
+}
+
+impl Drop for Server {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn start_server(args: &[&str]) -> Server {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_server"))
+        .args(["--port", "0"])
+        .args(args)
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("Failed to run server");
+    let mut stderr = BufReader::new(child.stderr.take().unwrap());
+    let mut line = String::new();
+    while !line.starts_with("Listening on") {
+        line.clear();
+        assert_ne!(stderr.read_line(&mut line).unwrap(), 0, "Server exited");
+    }
+    let port = line.trim().rsplit(':').next().unwrap().to_string();
+    Server {
+        child,
+        addr: format!("127.0.0.1:{port}"),
+        _stderr: stderr,
+    }
+}
+
+/// Sends one request with extra `headers` and returns the status code, the
+/// response head and the body.
+fn exchange(
+    server: &Server,
+    method: &str,
+    path: &str,
+    headers: &[&str],
+    body: &str,
+) -> (u16, String, String) {
+    let mut stream = TcpStream::connect(&server.addr).unwrap();
+    let headers: String = headers.iter().map(|h| format!("{h}\r\n")).collect();
+    write!(
+        stream,
+        "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+         {headers}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+    let status = head.split(' ').nth(1).unwrap().parse().unwrap();
+    (status, head.to_ascii_lowercase(), body.to_string())
+}
+
+/// Sends one request and returns the status code and JSON body.
+fn request(server: &Server, method: &str, path: &str, body: &str) -> (u16, serde_json::Value) {
+    let (status, _, body) = exchange(server, method, path, &[], body);
+    (status, serde_json::from_str(&body).unwrap())
+}
+
+#[test]
+fn process_json_returns_books_and_trades() {
+    let server = start_server(&[]);
+    let orders = r#"[
+      {"type_op":"CREATE","account_id":"1","amount":"1","order_id":"1","pair":"BTC/USDC","limit_price":"100","side":"SELL"},
+      {"type_op":"CREATE","account_id":"2","amount":"0.4","order_id":"2","pair":"BTC/USDC","limit_price":"100","side":"BUY"}
+    ]"#;
+    let (status, doc) = request(&server, "POST", "/api/process-json", orders);
+    assert_eq!(status, 200);
+    assert_eq!(doc["orderbooks"][0]["asks"][0]["remaining"], "0.6");
+    assert_eq!(doc["trades"][0]["amount"], "0.4");
+
+    let (status, _) = request(&server, "POST", "/api/process-json", "[]");
+    assert_eq!(status, 400);
+
+    let allowed = "access-control-allow-origin: http://localhost:3000";
+    let (_, head, _) = exchange(
+        &server,
+        "POST",
+        "/api/process-json",
+        &["Origin: http://localhost:3000"],
+        orders,
+    );
+    assert!(head.contains(allowed), "{head}");
+    let (_, head, _) = exchange(
+        &server,
+        "POST",
+        "/api/process-json",
+        &["Origin: https://elsewhere.example"],
+        orders,
+    );
+    assert!(!head.contains("access-control-allow-origin"), "{head}");
+
+    // The docker compose frontend may send the admin token across origins.
+    let preflight = [
+        "Origin: http://localhost:4000",
+        "Access-Control-Request-Method: POST",
+        "Access-Control-Request-Headers: authorization, content-type",
+    ];
+    let (status, head, _) = exchange(&server, "OPTIONS", "/api/instruments/halt", &preflight, "");
+    assert_eq!(status, 200);
+    assert!(
+        head.contains("access-control-allow-origin: http://localhost:4000"),
+        "{head}"
+    );
+    assert!(head.contains("authorization"), "{head}");
+}
+
+#[test]
+fn orders_accumulate_in_the_live_book() {
+    let server = start_server(&[]);
+    let sell = r#"{"type_op":"CREATE","account_id":"1","amount":"2","order_id":"1","pair":"BTC/USDC","limit_price":"100","side":"SELL"}"#;
+    let (status, doc) = request(&server, "POST", "/api/orders", sell);
+    assert_eq!(status, 200);
+    assert_eq!(doc["executionReports"][0]["execType"], "ACK");
+
+    let buy = r#"{"type_op":"CREATE","account_id":"2","amount":"0.5","order_id":"2","pair":"BTC/USDC","limit_price":"100","side":"BUY"}"#;
+    let (status, doc) = request(&server, "POST", "/api/orders", buy);
+    assert_eq!(status, 200);
+    assert_eq!(doc["trades"].as_array().unwrap().len(), 1);
+
+    let (status, doc) = request(&server, "POST", "/api/orders", buy);
+    assert_eq!(status, 422);
+    assert_eq!(doc["executionReports"][0]["reason"], "DUPLICATE_ORDER_ID");
+
+    let (status, book) = request(&server, "GET", "/api/orderbook?pair=BTC/USDC", "");
+    assert_eq!(status, 200);
+    assert_eq!(book["asks"][0]["remaining"], "1.5");
+
+    let sweep = "/api/sweep?pair=BTC/USDC&side=BUY&amount=2";
+    let (status, estimate) = request(&server, "GET", sweep, "");
+    assert_eq!(status, 200);
+    assert_eq!(estimate["filled"], "1.5");
+    assert_eq!(estimate["unfilled"], "0.5");
+    let (_, book) = request(&server, "GET", "/api/orderbook?pair=BTC/USDC", "");
+    assert_eq!(book["asks"][0]["remaining"], "1.5", "Sweeps don't trade");
+    let (status, _) = request(&server, "GET", &sweep.replace("=2", "=-1"), "");
+    assert_eq!(status, 400);
+
+    let (status, _) = request(&server, "GET", "/api/orderbook?pair=ETH/USDC", "");
+    assert_eq!(status, 404);
+}
+
+/// Level totals by price, as `(quantity, orders)`.
+type Levels = BTreeMap<String, (f64, u64)>;
+
+fn apply_levels(levels: &mut Levels, update: &serde_json::Value) {
+    for level in update.as_array().unwrap() {
+        let price = level["price"].as_str().unwrap().to_string();
+        let quantity: f64 = level["quantity"].as_str().unwrap().parse().unwrap();
+        if quantity == 0.0 {
+            levels.remove(&price);
+        } else {
+            levels.insert(price, (quantity, level["orders"].as_u64().unwrap()));
+        }
+    }
+}
+
+fn aggregate(orders: &serde_json::Value) -> Levels {
+    let mut levels = Levels::new();
+    for order in orders.as_array().unwrap() {
+        let level = levels
+            .entry(order["price"].as_str().unwrap().to_string())
+            .or_default();
+        level.0 += order["remaining"].as_str().unwrap().parse::<f64>().unwrap();
+        level.1 += 1;
+    }
+    levels
+}
+
+#[test]
+fn feed_snapshot_and_updates_rebuild_the_book() {
+    let server = start_server(&[]);
+    let order = |id: &str, side: &str, amount: &str, price: &str| {
+        format!(
+            r#"{{"type_op":"CREATE","account_id":"{id}","amount":"{amount}","order_id":"{id}","pair":"BTC/USDC","limit_price":"{price}","side":"{side}"}}"#
+        )
+    };
+    request(
+        &server,
+        "POST",
+        "/api/orders",
+        &order("1", "SELL", "1", "101"),
+    );
+    request(
+        &server,
+        "POST",
+        "/api/orders",
+        &order("2", "SELL", "2", "102"),
+    );
+
+    let (mut socket, _) = tungstenite::connect(format!("ws://{}/ws", server.addr)).unwrap();
+    socket
+        .send(r#"{"op":"subscribe","pair":"BTC/USDC"}"#.into())
+        .unwrap();
+    let mut read = || -> serde_json::Value {
+        let frame = socket.read().unwrap();
+        serde_json::from_str(frame.to_text().unwrap()).unwrap()
+    };
+    let snapshot = read();
+    assert_eq!(snapshot["type"], "snapshot");
+    assert_eq!(snapshot["seq"], 2);
+    let (mut bids, mut asks) = (Levels::new(), Levels::new());
+    apply_levels(&mut bids, &snapshot["bids"]);
+    apply_levels(&mut asks, &snapshot["asks"]);
+
+    request(
+        &server,
+        "POST",
+        "/api/orders",
+        &order("3", "SELL", "1", "101"),
+    );
+    request(
+        &server,
+        "POST",
+        "/api/orders",
+        &order("4", "BUY", "1.5", "101"),
+    );
+    request(
+        &server,
+        "POST",
+        "/api/orders",
+        &order("5", "BUY", "1", "99"),
+    );
+
+    let mut seq = snapshot["seq"].as_u64().unwrap();
+    let mut trades = 0;
+    while seq < 5 {
+        let message = read();
+        match message["type"].as_str().unwrap() {
+            "trade" => trades += 1,
+            "depth" => {
+                assert_eq!(message["seq"].as_u64().unwrap(), seq + 1, "Gapless");
+                seq += 1;
+                apply_levels(&mut bids, &message["bids"]);
+                apply_levels(&mut asks, &message["asks"]);
+            }
+            other => panic!("Unexpected {other} message"),
+        }
+    }
+    assert_eq!(trades, 2);
+
+    let (_, book) = request(&server, "GET", "/api/orderbook?pair=BTC/USDC", "");
+    assert_eq!(bids, aggregate(&book["bids"]));
+    assert_eq!(asks, aggregate(&book["asks"]));
+}
+
+#[test]
+fn restart_restores_the_live_book_from_snapshot_and_journal() {
+    let dir = std::env::temp_dir().join(format!("server-restore-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let journal = dir.join("journal.log");
+    let snapshots = dir.join("snapshots");
+    let args = [
+        "--journal",
+        journal.to_str().unwrap(),
+        "--snapshots",
+        snapshots.to_str().unwrap(),
+    ];
+    let order = |id: &str, side: &str, price: &str| {
+        format!(
+            r#"{{"type_op":"CREATE","account_id":"{id}","amount":"1","order_id":"{id}","pair":"BTC/USDC","limit_price":"{price}","side":"{side}"}}"#
+        )
+    };
+
+    let server = start_server(&args);
+    request(&server, "POST", "/api/orders", &order("1", "SELL", "101"));
+    request(&server, "POST", "/api/orders", &order("2", "SELL", "102"));
+    let (status, _) = request(&server, "POST", "/api/snapshot", "");
+    assert_eq!(status, 200);
+    request(&server, "POST", "/api/orders", &order("3", "BUY", "101"));
+    let (_, before) = request(&server, "GET", "/api/orderbook?pair=BTC/USDC", "");
+    drop(server);
+
+    let server = start_server(&args);
+    let (_, after) = request(&server, "GET", "/api/orderbook?pair=BTC/USDC", "");
+    assert_eq!(after, before);
+    assert_eq!(after["asks"].as_array().unwrap().len(), 1);
+    drop(server);
+    let _ = std::fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn admin_halts_resumes_and_delists_listed_pairs() {
+    let dir = std::env::temp_dir().join(format!("server-admin-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let instruments = dir.join("instruments.json");
+    std::fs::write(&instruments, r#"{"BTC/USDC": {"tick_size": "0.5"}}"#).unwrap();
+    let args = ["--instruments", instruments.to_str().unwrap()];
+    let server = start_server(&args);
+    let (status, _) = request(&server, "POST", "/api/instruments/halt", "{}");
+    assert_eq!(status, 403, "No admin token configured");
+    drop(server);
+
+    let server = start_server(&[&args[..], &["--admin-token", "s3cret"]].concat());
+    let admin = |action: &str, body: &str| {
+        let path = format!("/api/instruments/{action}");
+        let auth = "Authorization: Bearer s3cret";
+        let (status, _, body) = exchange(&server, "POST", &path, &[auth], body);
+        (
+            status,
+            serde_json::from_str::<serde_json::Value>(&body).unwrap(),
+        )
+    };
+    let order = |id: &str, pair: &str| {
+        format!(
+            r#"{{"type_op":"CREATE","account_id":"{id}","amount":"1","order_id":"{id}","pair":"{pair}","limit_price":"100","side":"SELL"}}"#
+        )
+    };
+    let pair = r#"{"pair":"BTC/USDC"}"#;
+
+    let (_, listed) = request(&server, "GET", "/api/instruments", "");
+    assert_eq!(listed[0]["base"], "BTC");
+    assert_eq!(listed[0]["quote"], "USDC");
+    assert_eq!(listed[0]["status"], "TRADING");
+    assert_eq!(listed[0]["rules"]["tick_size"], "0.5");
+
+    let (status, doc) = request(&server, "POST", "/api/orders", &order("1", "BTC/USCD"));
+    assert_eq!(status, 422);
+    assert_eq!(doc["executionReports"][0]["reason"], "UNKNOWN_PAIR");
+    request(&server, "POST", "/api/orders", &order("2", "BTC/USDC"));
+
+    let (status, _) = request(&server, "POST", "/api/instruments/halt", pair);
+    assert_eq!(status, 401, "Admin routes need the token");
+    let (status, doc) = admin("halt", pair);
+    assert_eq!(status, 200);
+    assert_eq!(doc["instrument"]["status"], "HALTED");
+    let (status, doc) = request(&server, "POST", "/api/orders", &order("3", "BTC/USDC"));
+    assert_eq!(status, 422);
+    assert_eq!(doc["executionReports"][0]["reason"], "PAIR_HALTED");
+    let (_, doc) = admin("resume", pair);
+    assert_eq!(doc["instrument"]["status"], "TRADING");
+
+    let (status, doc) = admin("delist", pair);
+    assert_eq!(status, 200);
+    assert_eq!(doc["instrument"]["status"], "DELISTED");
+    assert_eq!(doc["executionReports"][0]["orderId"], "2");
+    assert_eq!(doc["executionReports"][0]["reason"], "INSTRUMENT_DELISTED");
+    let (_, book) = request(&server, "GET", "/api/orderbook?pair=BTC/USDC", "");
+    assert!(book["asks"].as_array().unwrap().is_empty());
+
+    let (status, _) = admin("resume", pair);
+    assert_eq!(status, 409);
+    let unlisted = r#"{"pair":"ETH/USDC"}"#;
+    let (status, _) = admin("halt", unlisted);
+    assert_eq!(status, 404);
+    drop(server);
+    let _ = std::fs::remove_dir_all(&dir);
+}